@@ -0,0 +1,534 @@
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{
+    parse_macro_input,
+    punctuated::Punctuated,
+    token::Comma,
+    Attribute, Data, DataStruct, DeriveInput, Error, Field, Fields, Index, Member, Meta, NestedMeta,
+};
+
+static ROLLBACK_ATTRIBUTE_NAME: &str = "rollback";
+
+#[derive(Default)]
+struct ContainerArgs{
+    non_rolling: bool,
+    mirror: bool,
+    clone: bool,
+}
+
+#[derive(Default)]
+struct FieldArgs{
+    entity: bool,
+    skip: bool,
+}
+
+enum StructType{
+    Named,
+    Tuple,
+}
+
+/// Derives Reflect and RollbackType for a struct.
+///
+/// Container attributes:
+/// * `#[rollback(non_rolling)]` registers the type as local-only, it is never snapshotted.
+/// * `#[rollback(mirror)]` copies the component onto the synced outer entity, requires Clone.
+/// * `#[rollback(clone)]` snapshots through Clone, so the type doesn't need Default or FromWorld.
+///
+/// Field attributes:
+/// * `#[rollback(entity)]` remaps the field when a world is cloned, for Entity, Option<Entity> and Vec<Entity>.
+/// * `#[rollback(skip)]` leaves the field out of reflection, it is rebuilt with FromWorld on restore.
+///   It can't be combined with `#[rollback(clone)]`.
+#[proc_macro_derive(Rollback, attributes(rollback))]
+pub fn derive_rollback(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    match impl_rollback(&ast){
+        Ok(tokens) => TokenStream::from(tokens),
+        Err(err) => TokenStream::from(err.to_compile_error()),
+    }
+}
+
+fn impl_rollback(ast: &DeriveInput) -> Result<proc_macro2::TokenStream, Error>{
+    let unit_struct_punctuated = Punctuated::<Field, Comma>::new();
+    let (fields, struct_type) = match &ast.data{
+        Data::Struct(DataStruct{
+            fields: Fields::Named(fields),
+            ..
+        }) => (&fields.named, StructType::Named),
+        Data::Struct(DataStruct{
+            fields: Fields::Unnamed(fields),
+            ..
+        }) => (&fields.unnamed, StructType::Tuple),
+        Data::Struct(DataStruct{
+            fields: Fields::Unit,
+            ..
+        }) => (&unit_struct_punctuated, StructType::Named),
+        _ => return Err(Error::new(Span::call_site(), "Rollback can only be derived for structs")),
+    };
+
+    let container_args = parse_container_args(&ast.attrs)?;
+
+    let mut active_fields = Vec::new();
+    let mut active_types = Vec::new();
+    let mut entity_fields = Vec::new();
+    let mut skipped_fields = Vec::new();
+    for (index, field) in fields.iter().enumerate(){
+        let field_args = parse_field_args(&field.attrs)?;
+        let member = field
+            .ident
+            .as_ref()
+            .map(|ident| Member::Named(ident.clone()))
+            .unwrap_or_else(|| Member::Unnamed(Index::from(index)));
+        if field_args.skip && field_args.entity{
+            return Err(Error::new_spanned(field, "a field can't be both skipped and entity mapped"));
+        }
+        if field_args.skip && container_args.clone{
+            return Err(Error::new_spanned(field, "a field can't be skipped on a type snapshotted through Clone"));
+        }
+        if field_args.entity{
+            entity_fields.push(member.clone());
+        }
+        if field_args.skip{
+            skipped_fields.push(member.clone());
+        }
+        if !field_args.skip{
+            let name = field
+                .ident
+                .as_ref()
+                .map(|ident| ident.to_string())
+                .unwrap_or_else(|| index.to_string());
+            active_fields.push((member, name));
+            active_types.push(&field.ty);
+        }
+    }
+
+    let reflect_impl = match struct_type{
+        StructType::Named => impl_struct_reflect(ast, &active_fields),
+        StructType::Tuple => impl_tuple_struct_reflect(ast, &active_fields),
+    };
+
+    let type_name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    // Rolling types with skipped fields tell the Clone path how to rebuild them.
+    let rebuilds_skipped_fields = !skipped_fields.is_empty() && !container_args.non_rolling;
+    let get_type_registration_impl = impl_get_type_registration(ast, rebuilds_skipped_fields);
+
+    let skipped_fields_impl = if rebuilds_skipped_fields{
+        quote!{
+            impl #impl_generics ::bevy_rollback::rollback_type::SkippedFields for #type_name #ty_generics #where_clause {
+                fn reset_skipped(&mut self, fresh: Self) {
+                    #(self.#skipped_fields = fresh.#skipped_fields;)*
+                }
+            }
+        }
+    } else {
+        quote!{}
+    };
+
+    let map_entities_impl = if entity_fields.is_empty(){
+        quote!{}
+    } else {
+        quote!{
+            impl #impl_generics ::bevy::ecs::entity::MapEntities for #type_name #ty_generics #where_clause {
+                fn map_entities(&mut self, entity_map: &::bevy::ecs::entity::EntityMap) -> Result<(), ::bevy::ecs::entity::MapEntitiesError> {
+                    #(::bevy_rollback::rollback_type::MapEntity::map_entity(&mut self.#entity_fields, entity_map)?;)*
+                    Ok(())
+                }
+            }
+        }
+    };
+
+    // Types snapshotted through Clone have no FromWorld to start from, so they are rebuilt field
+    // by field. Skipped fields are rejected above, every field is reflected.
+    let from_reflect_impl = if container_args.clone{
+        let field_idents = active_fields.iter().map(|(member, _)| member).collect::<Vec<_>>();
+        let from_reflect = match struct_type{
+            StructType::Named => {
+                let field_names = active_fields.iter().map(|(_, name)| name).collect::<Vec<_>>();
+                quote!{
+                    match reflect.reflect_ref(){
+                        ::bevy::reflect::ReflectRef::Struct(value) => Some(#type_name{
+                            #(#field_idents: ::bevy_rollback::rollback_type::FromReflect::from_reflect(value.field(#field_names)?)?,)*
+                        }),
+                        _ => None,
+                    }
+                }
+            },
+            StructType::Tuple => {
+                let field_indices = (0..active_fields.len()).collect::<Vec<usize>>();
+                quote!{
+                    match reflect.reflect_ref(){
+                        ::bevy::reflect::ReflectRef::TupleStruct(value) => Some(#type_name{
+                            #(#field_idents: ::bevy_rollback::rollback_type::FromReflect::from_reflect(value.field(#field_indices)?)?,)*
+                        }),
+                        _ => None,
+                    }
+                }
+            },
+        };
+        quote!{
+            impl #impl_generics ::bevy_rollback::rollback_type::FromReflect for #type_name #ty_generics #where_clause {
+                fn from_reflect(reflect: &dyn ::bevy::reflect::Reflect) -> Option<Self> {
+                    #from_reflect
+                }
+            }
+        }
+    } else {
+        quote!{}
+    };
+
+    let register = if container_args.non_rolling{
+        quote!{ registry.register_non_rolling::<Self>(); }
+    } else if container_args.clone && entity_fields.is_empty(){
+        quote!{ registry.register_reflect_clone::<Self>(); }
+    } else if container_args.clone{
+        quote!{
+            registry.register_reflect_clone::<Self>();
+            registry.register_map_entities::<Self>();
+        }
+    } else if entity_fields.is_empty(){
+        quote!{ registry.register::<Self>(); }
+    } else {
+        quote!{ registry.register_entity_mappable::<Self>(); }
+    };
+
+    // Reflection numbers the fields of a tuple struct without the skipped ones.
+    let schema_names = match struct_type{
+        StructType::Named => active_fields.iter().map(|(_, name)| name.clone()).collect::<Vec<_>>(),
+        StructType::Tuple => (0..active_fields.len()).map(|index| index.to_string()).collect(),
+    };
+    let schema_kind = match struct_type{
+        StructType::Named => "struct",
+        StructType::Tuple => "tuple_struct",
+    };
+    let schema = if container_args.non_rolling{
+        quote!{}
+    } else {
+        quote!{
+            registry.set_schema::<Self>(::bevy_rollback::schema::ReflectSchema::new(||{
+                Ok(::bevy_rollback::schema::TypeSchema::from_fields(
+                    ::std::any::type_name::<Self>(),
+                    #schema_kind,
+                    &[#((#schema_names, ::std::any::type_name::<#active_types>()),)*],
+                ))
+            }));
+        }
+    };
+
+    let mirror = if container_args.mirror{
+        quote!{ registry.register_mirrored::<Self>(); }
+    } else {
+        quote!{}
+    };
+
+    Ok(quote!{
+        #get_type_registration_impl
+
+        #reflect_impl
+
+        #skipped_fields_impl
+
+        #map_entities_impl
+
+        #from_reflect_impl
+
+        impl #impl_generics ::bevy_rollback::rollback_type::RollbackType for #type_name #ty_generics #where_clause {
+            fn register_rollback(registry: &mut ::bevy_rollback::rollback_registry::RollbackRegistry) {
+                #register
+                #schema
+                #mirror
+            }
+        }
+    })
+}
+
+fn rollback_args(attrs: &[Attribute]) -> Result<Vec<NestedMeta>, Error>{
+    let mut args = Vec::new();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident(ROLLBACK_ATTRIBUTE_NAME)){
+        match attr.parse_meta()?{
+            Meta::List(meta_list) => args.extend(meta_list.nested),
+            meta => return Err(Error::new_spanned(meta, "expected #[rollback(...)]")),
+        }
+    }
+    Ok(args)
+}
+
+fn parse_container_args(attrs: &[Attribute]) -> Result<ContainerArgs, Error>{
+    let mut container_args = ContainerArgs::default();
+    for arg in rollback_args(attrs)?{
+        match &arg{
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("non_rolling") => container_args.non_rolling = true,
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("mirror") => container_args.mirror = true,
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("clone") => container_args.clone = true,
+            _ => return Err(Error::new_spanned(arg, "unknown rollback attribute, expected `non_rolling`, `mirror` or `clone`")),
+        }
+    }
+    Ok(container_args)
+}
+
+fn parse_field_args(attrs: &[Attribute]) -> Result<FieldArgs, Error>{
+    let mut field_args = FieldArgs::default();
+    for arg in rollback_args(attrs)?{
+        match &arg{
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("entity") => field_args.entity = true,
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => field_args.skip = true,
+            _ => return Err(Error::new_spanned(arg, "unknown rollback attribute, expected `entity` or `skip`")),
+        }
+    }
+    Ok(field_args)
+}
+
+fn impl_get_type_registration(ast: &DeriveInput, rebuilds_skipped_fields: bool) -> proc_macro2::TokenStream{
+    let type_name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    let registration = if rebuilds_skipped_fields{
+        quote!{
+            let mut registration = ::bevy::reflect::TypeRegistration::of::<#type_name #ty_generics>();
+            registration.insert(<::bevy_rollback::reflect_resource::ReflectSkippedFields as ::bevy::reflect::FromType<Self>>::from_type());
+            registration
+        }
+    } else {
+        quote!{ ::bevy::reflect::TypeRegistration::of::<#type_name #ty_generics>() }
+    };
+    quote!{
+        impl #impl_generics ::bevy::reflect::GetTypeRegistration for #type_name #ty_generics #where_clause {
+            fn get_type_registration() -> ::bevy::reflect::TypeRegistration {
+                #registration
+            }
+        }
+    }
+}
+
+fn impl_struct_reflect(ast: &DeriveInput, active_fields: &[(Member, String)]) -> proc_macro2::TokenStream{
+    let type_name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let field_idents = active_fields.iter().map(|(member, _)| member).collect::<Vec<_>>();
+    let field_names = active_fields.iter().map(|(_, name)| name).collect::<Vec<_>>();
+    let field_count = active_fields.len();
+    let field_indices = (0..field_count).collect::<Vec<usize>>();
+
+    quote!{
+        impl #impl_generics ::bevy::reflect::Struct for #type_name #ty_generics #where_clause {
+            fn field(&self, name: &str) -> Option<&dyn ::bevy::reflect::Reflect> {
+                match name {
+                    #(#field_names => Some(&self.#field_idents),)*
+                    _ => None,
+                }
+            }
+
+            fn field_mut(&mut self, name: &str) -> Option<&mut dyn ::bevy::reflect::Reflect> {
+                match name {
+                    #(#field_names => Some(&mut self.#field_idents),)*
+                    _ => None,
+                }
+            }
+
+            fn field_at(&self, index: usize) -> Option<&dyn ::bevy::reflect::Reflect> {
+                match index {
+                    #(#field_indices => Some(&self.#field_idents),)*
+                    _ => None,
+                }
+            }
+
+            fn field_at_mut(&mut self, index: usize) -> Option<&mut dyn ::bevy::reflect::Reflect> {
+                match index {
+                    #(#field_indices => Some(&mut self.#field_idents),)*
+                    _ => None,
+                }
+            }
+
+            fn name_at(&self, index: usize) -> Option<&str> {
+                match index {
+                    #(#field_indices => Some(#field_names),)*
+                    _ => None,
+                }
+            }
+
+            fn field_len(&self) -> usize {
+                #field_count
+            }
+
+            fn iter_fields(&self) -> ::bevy::reflect::FieldIter {
+                ::bevy::reflect::FieldIter::new(self)
+            }
+
+            fn clone_dynamic(&self) -> ::bevy::reflect::DynamicStruct {
+                let mut dynamic = ::bevy::reflect::DynamicStruct::default();
+                dynamic.set_name(::bevy::reflect::Reflect::type_name(self).to_string());
+                #(dynamic.insert_boxed(#field_names, ::bevy::reflect::Reflect::clone_value(&self.#field_idents));)*
+                dynamic
+            }
+        }
+
+        // SAFE: any and any_mut both return self
+        unsafe impl #impl_generics ::bevy::reflect::Reflect for #type_name #ty_generics #where_clause {
+            #[inline]
+            fn type_name(&self) -> &str {
+                std::any::type_name::<Self>()
+            }
+
+            #[inline]
+            fn any(&self) -> &dyn std::any::Any {
+                self
+            }
+
+            #[inline]
+            fn any_mut(&mut self) -> &mut dyn std::any::Any {
+                self
+            }
+
+            #[inline]
+            fn clone_value(&self) -> Box<dyn ::bevy::reflect::Reflect> {
+                use ::bevy::reflect::Struct;
+                Box::new(self.clone_dynamic())
+            }
+
+            #[inline]
+            fn set(&mut self, value: Box<dyn ::bevy::reflect::Reflect>) -> Result<(), Box<dyn ::bevy::reflect::Reflect>> {
+                *self = value.take()?;
+                Ok(())
+            }
+
+            #[inline]
+            fn apply(&mut self, value: &dyn ::bevy::reflect::Reflect) {
+                use ::bevy::reflect::Struct;
+                if let ::bevy::reflect::ReflectRef::Struct(struct_value) = value.reflect_ref() {
+                    for (i, value) in struct_value.iter_fields().enumerate() {
+                        let name = struct_value.name_at(i).unwrap();
+                        self.field_mut(name).map(|v| v.apply(value));
+                    }
+                } else {
+                    panic!("Attempted to apply non-struct type to struct type.");
+                }
+            }
+
+            fn reflect_ref(&self) -> ::bevy::reflect::ReflectRef {
+                ::bevy::reflect::ReflectRef::Struct(self)
+            }
+
+            fn reflect_mut(&mut self) -> ::bevy::reflect::ReflectMut {
+                ::bevy::reflect::ReflectMut::Struct(self)
+            }
+
+            fn serializable(&self) -> Option<::bevy::reflect::serde::Serializable> {
+                None
+            }
+
+            fn reflect_hash(&self) -> Option<u64> {
+                None
+            }
+
+            fn reflect_partial_eq(&self, value: &dyn ::bevy::reflect::Reflect) -> Option<bool> {
+                ::bevy::reflect::struct_partial_eq(self, value)
+            }
+        }
+    }
+}
+
+fn impl_tuple_struct_reflect(ast: &DeriveInput, active_fields: &[(Member, String)]) -> proc_macro2::TokenStream{
+    let type_name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let field_idents = active_fields.iter().map(|(member, _)| member).collect::<Vec<_>>();
+    let field_count = active_fields.len();
+    let field_indices = (0..field_count).collect::<Vec<usize>>();
+
+    quote!{
+        impl #impl_generics ::bevy::reflect::TupleStruct for #type_name #ty_generics #where_clause {
+            fn field(&self, index: usize) -> Option<&dyn ::bevy::reflect::Reflect> {
+                match index {
+                    #(#field_indices => Some(&self.#field_idents),)*
+                    _ => None,
+                }
+            }
+
+            fn field_mut(&mut self, index: usize) -> Option<&mut dyn ::bevy::reflect::Reflect> {
+                match index {
+                    #(#field_indices => Some(&mut self.#field_idents),)*
+                    _ => None,
+                }
+            }
+
+            fn field_len(&self) -> usize {
+                #field_count
+            }
+
+            fn iter_fields(&self) -> ::bevy::reflect::TupleStructFieldIter {
+                ::bevy::reflect::TupleStructFieldIter::new(self)
+            }
+
+            fn clone_dynamic(&self) -> ::bevy::reflect::DynamicTupleStruct {
+                let mut dynamic = ::bevy::reflect::DynamicTupleStruct::default();
+                dynamic.set_name(::bevy::reflect::Reflect::type_name(self).to_string());
+                #(dynamic.insert_boxed(::bevy::reflect::Reflect::clone_value(&self.#field_idents));)*
+                dynamic
+            }
+        }
+
+        // SAFE: any and any_mut both return self
+        unsafe impl #impl_generics ::bevy::reflect::Reflect for #type_name #ty_generics #where_clause {
+            #[inline]
+            fn type_name(&self) -> &str {
+                std::any::type_name::<Self>()
+            }
+
+            #[inline]
+            fn any(&self) -> &dyn std::any::Any {
+                self
+            }
+
+            #[inline]
+            fn any_mut(&mut self) -> &mut dyn std::any::Any {
+                self
+            }
+
+            #[inline]
+            fn clone_value(&self) -> Box<dyn ::bevy::reflect::Reflect> {
+                use ::bevy::reflect::TupleStruct;
+                Box::new(self.clone_dynamic())
+            }
+
+            #[inline]
+            fn set(&mut self, value: Box<dyn ::bevy::reflect::Reflect>) -> Result<(), Box<dyn ::bevy::reflect::Reflect>> {
+                *self = value.take()?;
+                Ok(())
+            }
+
+            #[inline]
+            fn apply(&mut self, value: &dyn ::bevy::reflect::Reflect) {
+                use ::bevy::reflect::TupleStruct;
+                if let ::bevy::reflect::ReflectRef::TupleStruct(struct_value) = value.reflect_ref() {
+                    for (i, value) in struct_value.iter_fields().enumerate() {
+                        self.field_mut(i).map(|v| v.apply(value));
+                    }
+                } else {
+                    panic!("Attempted to apply non-TupleStruct type to TupleStruct type.");
+                }
+            }
+
+            fn reflect_ref(&self) -> ::bevy::reflect::ReflectRef {
+                ::bevy::reflect::ReflectRef::TupleStruct(self)
+            }
+
+            fn reflect_mut(&mut self) -> ::bevy::reflect::ReflectMut {
+                ::bevy::reflect::ReflectMut::TupleStruct(self)
+            }
+
+            fn serializable(&self) -> Option<::bevy::reflect::serde::Serializable> {
+                None
+            }
+
+            fn reflect_hash(&self) -> Option<u64> {
+                None
+            }
+
+            fn reflect_partial_eq(&self, value: &dyn ::bevy::reflect::Reflect) -> Option<bool> {
+                ::bevy::reflect::tuple_struct_partial_eq(self, value)
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use bevy::prelude::*;
+use bevy::ecs::schedule::ShouldRun;
+use bevy::reflect::Reflect;
+use crate::RollbackWorld;
+
+/// The number of ticks the rollback stages run per second of simulated time. It lives in the
+/// RollbackWorld so snapshots and replays carry it, change it from a rollback system or an
+/// override to keep every peer on the same rate.
+#[derive(Reflect, Clone, Copy, Debug, PartialEq)]
+pub struct RollbackTickRate{
+    pub rate: f64,
+}
+
+impl Default for RollbackTickRate{
+    fn default() -> Self{
+        RollbackTickRate{
+            rate: 60.0,
+        }
+    }
+}
+
+/// Controls how the rollback stages advance: pausing, single stepping and slow motion.
+pub struct RollbackControl{
+    paused: bool,
+    steps: usize,
+    speed: f64,
+    restart: bool,
+}
+
+impl Default for RollbackControl{
+    fn default() -> Self{
+        RollbackControl{
+            paused: false,
+            steps: 0,
+            speed: 1.0,
+            restart: false,
+        }
+    }
+}
+
+impl RollbackControl{
+    pub fn pause(&mut self){
+        self.paused = true;
+    }
+
+    /// Resumes the simulation, dropping any steps that weren't taken yet.
+    pub fn resume(&mut self){
+        self.paused = false;
+        self.steps = 0;
+    }
+
+    pub fn is_paused(&self) -> bool{
+        self.paused
+    }
+
+    /// Runs the given number of ticks on the next frame while paused, does nothing otherwise.
+    pub fn step(&mut self, ticks: usize){
+        if self.paused{
+            self.steps += ticks;
+        }
+    }
+
+    /// Scales how fast simulated time passes, 0.5 runs the simulation at half speed.
+    pub fn set_speed(&mut self, speed: f64){
+        self.speed = speed.max(0.0);
+    }
+
+    pub fn speed(&self) -> f64{
+        self.speed
+    }
+
+    /// Starts the match over at the beginning of the next frame: the RollbackWorld is cleared down to
+    /// the resources inserted with insert_rollback_resource and the current tick rate, the rollback
+    /// event queues and the RollbackBuffer emptied and the rollback startup schedule run again.
+    pub fn restart(&mut self){
+        self.restart = true;
+    }
+
+    pub(crate) fn take_restart(&mut self) -> bool{
+        std::mem::take(&mut self.restart)
+    }
+}
+
+/// How the rollback stages catch up after a hitch left them several ticks behind.
+#[derive(Default, Clone, Debug)]
+pub struct CatchUpPolicy{
+    /// The most ticks run in a single render frame, the time for any further ticks is dropped.
+    pub max_ticks: Option<usize>,
+    /// Only snapshot the last tick run in a render frame, and one every half buffer during long
+    /// catch-ups. A rollback into a skipped tick resimulates from the closest snapshot before it.
+    pub skip_intermediate_snapshots: bool,
+}
+
+/// Sent when the catch-up policy drops ticks the simulation fell behind on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DroppedTicks{
+    pub ticks: usize,
+    pub seconds: f64,
+}
+
+/// Decides how many ticks the rollback stages run in the current frame, so all of them agree.
+#[derive(Default)]
+pub(crate) struct RollbackClock{
+    accumulator: f64,
+    ticks: usize,
+    ran: usize,
+}
+
+impl RollbackClock{
+    pub(crate) fn advance(
+        &mut self,
+        delta: f64,
+        tick_rate: RollbackTickRate,
+        control: &mut RollbackControl,
+        catch_up: &CatchUpPolicy,
+    ) -> Option<DroppedTicks>{
+        self.ran = 0;
+        if control.paused{
+            self.accumulator = 0.0;
+            self.ticks = control.steps;
+            control.steps = 0;
+            return None;
+        }
+
+        let step = 1.0 / tick_rate.rate;
+        self.accumulator += delta * control.speed;
+        self.ticks = (self.accumulator / step) as usize;
+        self.accumulator -= self.ticks as f64 * step;
+
+        match catch_up.max_ticks{
+            Some(max_ticks) if self.ticks > max_ticks => {
+                let dropped = self.ticks - max_ticks;
+                self.ticks = max_ticks;
+                Some(DroppedTicks{
+                    ticks: dropped,
+                    seconds: dropped as f64 * step,
+                })
+            },
+            _ => None,
+        }
+    }
+
+    pub(crate) fn ticks(&self) -> usize{
+        self.ticks
+    }
+
+    /// Counts a tick as run, returning whether more ticks follow it in this frame.
+    pub(crate) fn run_tick(&mut self) -> bool{
+        self.ran += 1;
+        self.ran < self.ticks
+    }
+}
+
+pub(crate) fn advance_rollback_clock(
+    time: Res<Time>,
+    rollback_world: Res<RollbackWorld>,
+    catch_up: Res<CatchUpPolicy>,
+    mut control: ResMut<RollbackControl>,
+    mut clock: ResMut<RollbackClock>,
+    mut dropped_ticks: EventWriter<DroppedTicks>,
+){
+    let tick_rate = rollback_world
+        .get_resource::<RollbackTickRate>()
+        .cloned()
+        .unwrap_or_default();
+    if let Some(dropped) = clock.advance(time.delta_seconds_f64(), tick_rate, &mut control, &catch_up){
+        dropped_ticks.send(dropped);
+    }
+}
+
+/// The run criteria of every RollbackStage, running the stage once per tick of the RollbackClock.
+pub(crate) fn run_rollback_ticks(
+    clock: Res<RollbackClock>,
+    mut ran: Local<usize>,
+) -> ShouldRun{
+    if *ran < clock.ticks(){
+        *ran += 1;
+        ShouldRun::YesAndCheckAgain
+    }else{
+        *ran = 0;
+        ShouldRun::No
+    }
+}
@@ -1,6 +1,12 @@
 use bevy::ecs::component::ComponentInfo;
+use bevy::ecs::entity::MapEntitiesError;
 
 #[derive(Debug)]
 pub enum RollbackError{
-    UnregisteredType(String)
+    UnregisteredType(String),
+    MapEntities(String, MapEntitiesError),
+    Migration(String),
+    Schema(String),
+    MissingSnapshot(usize),
+    Serialization(String),
 }
\ No newline at end of file
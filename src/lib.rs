@@ -1,10 +1,11 @@
 use crate::rollback_schedule::RollbackStartupSchedule;
-use bevy::core::FixedTimestep;
 use crate::rollback_buffer::RollbackBuffer;
-use crate::rollback_registry::RollbackRegistry;
+use crate::rollback_registry::{RollbackRegistry, UnregisteredPolicy};
 use bevy::prelude::*;
 use rollback_schedule::RollbackSchedule;
-use system::{rollback_startup, rollback_system, sync_rollback_entities};
+use system::{rollback_startup, restart_rollback, rollback_system, sync_rollback_entities, sync_rollback_hierarchy, mirror_rollback_components, RollbackFailed, SyncSettings, SyncedDespawnEvent, SyncedEntityMap};
+use control::{advance_rollback_clock, run_rollback_ticks, CatchUpPolicy, DroppedTicks, RollbackClock, RollbackControl, RollbackTickRate};
+use side_effect::{deliver_side_effects, SideEffectEvent, SideEffectLedger, SideEffectSettings};
 use std::ops::{Deref, DerefMut};
 
 pub mod rollback_registry;
@@ -14,6 +15,19 @@ pub mod reflect_resource;
 pub mod rollback_buffer;
 pub mod rollback_schedule;
 pub mod system;
+pub mod spawn_key;
+pub mod rollback_type;
+pub mod schema;
+pub mod rollback_event;
+pub mod side_effect;
+pub mod run_criteria;
+pub mod control;
+
+pub use bevy_rollback_derive::Rollback;
+pub use rollback_schedule::RollbackScheduleStage;
+
+// Lets the code generated by #[derive(Rollback)] refer to this crate by name from inside it.
+extern crate self as bevy_rollback;
 
 
 pub struct RollbackWorld{
@@ -42,6 +56,24 @@ impl DerefMut for RollbackWorld{
     }
 }
 
+/// The frame being simulated, kept in the RollbackWorld by the rollback system and refreshed
+/// before every run of the RollbackSchedule. It is never part of a snapshot.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RollbackFrame{
+    pub frame: usize,
+    /// The frame has been simulated before and is being run again after a rollback.
+    pub is_resimulation: bool,
+    /// The latest frame that won't be rolled back again, if any.
+    pub confirmed_frame: Option<usize>,
+}
+
+impl RollbackFrame{
+    /// Whether this run of the frame is final.
+    pub fn is_confirmed(&self) -> bool{
+        self.confirmed_frame.is_some_and(|confirmed_frame| self.frame <= confirmed_frame)
+    }
+}
+
 #[derive(StageLabel, PartialEq, Eq, Hash, Clone, Debug)]
 pub enum RollbackStage{
     PreUpdate,
@@ -53,33 +85,87 @@ pub enum RollbackStage{
 pub struct RollbackPlugin{
     capacity: usize,
     rate: f64,
+    defer_despawn: bool,
+    confirmed_side_effects: bool,
+    catch_up: CatchUpPolicy,
+    unregistered_policy: UnregisteredPolicy,
 }
 
 impl RollbackPlugin{
     pub fn with_buffer_capcity(capacity: usize, rate: f64) -> Self{
         Self{
             capacity,
-            rate
+            rate,
+            defer_despawn: false,
+            confirmed_side_effects: false,
+            catch_up: CatchUpPolicy::default(),
+            unregistered_policy: UnregisteredPolicy::default(),
         }
     }
+
+    /// Choose what snapshots do with types that aren't registered, by default they fail.
+    pub fn with_unregistered_policy(mut self, policy: UnregisteredPolicy) -> Self{
+        self.unregistered_policy = policy;
+        self
+    }
+
+    /// Choose how the rollback stages catch up after a hitch, by default every missed tick is run.
+    pub fn with_catch_up_policy(mut self, catch_up: CatchUpPolicy) -> Self{
+        self.catch_up = catch_up;
+        self
+    }
+
+    /// Only fire side effects once the frame they happened in is confirmed.
+    pub fn with_confirmed_side_effects(mut self) -> Self{
+        self.confirmed_side_effects = true;
+        self
+    }
+
+    /// Keep outer entities with a SpawnKey around until the frame their target vanished in is confirmed.
+    pub fn with_deferred_despawn(mut self) -> Self{
+        self.defer_despawn = true;
+        self
+    }
 }
 
 impl Plugin for RollbackPlugin{
     fn build(&self, app: &mut AppBuilder) {
+        let mut registry = RollbackRegistry::default();
+        registry.set_unregistered_policy(self.unregistered_policy);
+
+        let mut rollback_world = RollbackWorld::default();
+        rollback_world.insert_resource(RollbackTickRate{rate: self.rate});
+
         app
             .insert_resource(RollbackBuffer::with_capacity(self.capacity))
-            .insert_resource(RollbackWorld::default())
-            .insert_resource(RollbackRegistry::default())
+            .insert_resource(rollback_world)
+            .insert_resource(registry)
             .insert_resource(RollbackSchedule::default())
             .insert_resource(RollbackStartupSchedule::default())
+            .insert_resource(RollbackControl::default())
+            .insert_resource(RollbackClock::default())
+            .insert_resource(self.catch_up.clone())
+            .insert_resource(SyncSettings{defer_despawn: self.defer_despawn})
+            .insert_resource(SyncedEntityMap::default())
+            .insert_resource(SideEffectSettings{confirmed_only: self.confirmed_side_effects})
+            .insert_resource(SideEffectLedger::default())
+            .add_event::<SyncedDespawnEvent>()
+            .add_event::<SideEffectEvent>()
+            .add_event::<DroppedTicks>()
+            .add_event::<RollbackFailed>()
             .add_stage_before(CoreStage::Update, RollbackStage::Update, SystemStage::parallel()
-                .with_run_criteria(FixedTimestep::steps_per_second(self.rate)))
+                .with_run_criteria(run_rollback_ticks.system()))
             .add_stage_before(RollbackStage::Update, RollbackStage::PreUpdate, SystemStage::parallel()
-                .with_run_criteria(FixedTimestep::steps_per_second(self.rate)))
+                .with_run_criteria(run_rollback_ticks.system()))
             .add_stage_after(RollbackStage::Update, RollbackStage::PostUpdate, SystemStage::parallel()
-                .with_run_criteria(FixedTimestep::steps_per_second(self.rate)))
+                .with_run_criteria(run_rollback_ticks.system()))
+            .add_system_to_stage(CoreStage::PreUpdate, restart_rollback.exclusive_system().at_start())
+            .add_system_to_stage(CoreStage::PreUpdate, advance_rollback_clock.system())
             .add_system_set_to_stage(RollbackStage::Update, SystemSet::new().with_system(rollback_system.system()).label("rollback"))
             .add_system_set_to_stage(RollbackStage::PostUpdate, SystemSet::new().with_system(sync_rollback_entities.system()).label("sync"))
+            .add_system_to_stage(RollbackStage::PostUpdate, deliver_side_effects.system())
+            .add_system_to_stage(RollbackStage::PostUpdate, mirror_rollback_components.exclusive_system().at_end())
+            .add_system_to_stage(CoreStage::PostUpdate, sync_rollback_hierarchy.exclusive_system().at_start())
             .add_startup_stage(RollbackStage::Startup, SystemStage::parallel())
             .add_startup_system_to_stage(RollbackStage::Startup, rollback_startup.system());
     }
@@ -89,20 +175,28 @@ impl Plugin for RollbackPlugin{
 mod tests {
     use bevy::tasks::ComputeTaskPool;
     use bevy::prelude::*;
+    use bevy::app::Events;
     use bevy::scene::{
         DynamicScene,
         serde::*,
     };
-    use bevy::ecs::entity::EntityMap;
+    use bevy::ecs::entity::{EntityMap, MapEntities, MapEntitiesError};
     use bevy::reflect::*;
     use ::serde::*;
 
-    use crate::rollback_registry::RollbackRegistry;
+    use crate::rollback_registry::{RollbackRegistry, UnregisteredPolicy};
     use crate::util::*;
-    use crate::RollbackWorld;
-    use crate::system::rollback_system;
-    use crate::rollback_schedule::RollbackSchedule;
+    use crate::schema::{RegistrySchema, SavedWorld};
+    use crate::reflect_resource::ReflectResource;
+    use crate::err::RollbackError;
+    use crate::{RollbackWorld, Rollback, RollbackScheduleStage, RollbackFrame};
+    use crate::system::{rollback_system, rollback_startup, restart_rollback, sync_rollback_entities, sync_rollback_hierarchy, RollbackFailed, InitialRollbackResources, Synced, SyncSettings, SyncedDespawnEvent, SyncedEntityMap, PendingDespawn};
+    use crate::spawn_key::{SpawnKey, SpawnKeyGenerator};
+    use crate::side_effect::{deliver_side_effects, SideEffectEmitter, SideEffectEvent, SideEffectKey, SideEffectLedger, SideEffectSettings};
+    use crate::rollback_schedule::{RollbackSchedule, RollbackStartupSchedule};
     use crate::rollback_buffer::RollbackBuffer;
+    use crate::run_criteria::{on_new_frame, on_resimulation, on_confirmed_frame};
+    use crate::control::{RollbackClock, RollbackControl, RollbackTickRate, CatchUpPolicy, DroppedTicks};
 
     #[test]
     fn resource_clone() {
@@ -191,6 +285,1226 @@ mod tests {
         assert_eq!(-101, *larger_world.get_resource::<RollbackWorld>().unwrap().get_resource::<isize>().unwrap());
     }
 
+    #[test]
+    fn spawn_key_stable(){
+        let mut generator = SpawnKeyGenerator::default();
+        generator.start_frame(3);
+        // Pinned so every build, and every peer, hands out the same keys.
+        assert_eq!(SpawnKey{source: 0xd9355d8dc38bb270, frame: 3, index: 0}, generator.next_key("spawner"));
+        assert_eq!(SpawnKey{source: 0xd9355d8dc38bb270, frame: 3, index: 1}, generator.next_key("spawner"));
+    }
+
+    #[test]
+    fn spawn_key_reconcile(){
+        let world = RollbackWorld::default();
+        let rollback_buffer = RollbackBuffer::with_capacity(10);
+        let mut rollback_schedule = RollbackSchedule::default();
+        let registry = RollbackRegistry::default();
+
+        rollback_schedule.add_stage("test", SystemStage::single_threaded());
+        rollback_schedule.add_system_to_stage("test", (|mut commands: Commands, frame: Res<RollbackFrame>, mut generator: ResMut<SpawnKeyGenerator>|{
+            if frame.frame == 3{
+                commands
+                    .spawn()
+                    .insert(generator.next_key("spawner"));
+            }
+        }).system());
+
+        let mut larger_world = World::default();
+        larger_world.insert_resource(world);
+        larger_world.insert_resource(rollback_buffer);
+        larger_world.insert_resource(rollback_schedule);
+        larger_world.insert_resource(registry);
+        larger_world.insert_resource(SyncSettings::default());
+        larger_world.insert_resource(Events::<SyncedDespawnEvent>::default());
+        larger_world.insert_resource(SyncedEntityMap::default());
+
+        let mut helper_stage = SystemStage::single_threaded();
+        helper_stage.add_system(rollback_system.system().label("rollback"));
+        helper_stage.add_system(sync_rollback_entities.system().after("rollback"));
+
+        for _ in 0..5{
+            helper_stage.run(&mut larger_world);
+        }
+
+        let (outer, old_target) = larger_world
+            .query::<(Entity, &Synced)>()
+            .iter(&larger_world)
+            .map(|(entity, synced)| (entity, synced.target))
+            .next()
+            .unwrap();
+
+        // Rolling back past frame 3 drops the spawn, resimulating spawns it again under the same key.
+        larger_world.get_resource_mut::<RollbackBuffer>().unwrap().add_overrides_relative(&3, Box::new(|| {}).system());
+        helper_stage.run(&mut larger_world);
+
+        let synced = larger_world.query::<(Entity, &Synced)>().iter(&larger_world).map(|(entity, _)| entity).collect::<Vec<_>>();
+        assert_eq!(vec![outer], synced);
+
+        let target = larger_world.get::<Synced>(outer).unwrap().target;
+        assert_ne!(old_target, target);
+        assert!(larger_world.get_resource::<RollbackWorld>().unwrap().get_entity(old_target).is_none());
+
+        let synced_entity_map = larger_world.get_resource::<SyncedEntityMap>().unwrap();
+        assert_eq!(Some(target), synced_entity_map.rollback_entity(outer));
+        assert_eq!(Some(outer), synced_entity_map.outer_entity(target));
+        assert_eq!(
+            Some(outer),
+            larger_world.get_resource::<RollbackWorld>().unwrap().get_resource::<SyncedEntityMap>().unwrap().outer_entity(target));
+    }
+
+    #[test]
+    fn deferred_despawn(){
+        let mut world = RollbackWorld::default();
+        let mut generator = SpawnKeyGenerator::default();
+
+        let target = world
+            .spawn()
+            .insert(generator.next_key("spawner"))
+            .id();
+
+        let mut larger_world = World::default();
+        larger_world.insert_resource(world);
+        larger_world.insert_resource(RollbackBuffer::with_capacity(10));
+        larger_world.insert_resource(SyncSettings{defer_despawn: true});
+        larger_world.insert_resource(Events::<SyncedDespawnEvent>::default());
+        larger_world.insert_resource(SyncedEntityMap::default());
+
+        let mut helper_stage = SystemStage::single_threaded();
+        helper_stage.add_system(sync_rollback_entities.system());
+        helper_stage.run(&mut larger_world);
+
+        larger_world.get_resource_mut::<RollbackWorld>().unwrap().despawn(target);
+        larger_world.get_resource_mut::<RollbackBuffer>().unwrap().inc_frame();
+        helper_stage.run(&mut larger_world);
+
+        assert_eq!(1, larger_world.query::<(&Synced, &PendingDespawn)>().iter(&larger_world).count());
+
+        larger_world.get_resource_mut::<RollbackBuffer>().unwrap().inc_frame();
+        helper_stage.run(&mut larger_world);
+        assert_eq!(1, larger_world.query::<(&Synced, &PendingDespawn)>().iter(&larger_world).count());
+
+        // Confirming the frame it vanished in despawns it before the frame leaves the buffer.
+        larger_world.get_resource_mut::<RollbackBuffer>().unwrap().confirm_frame(0);
+        helper_stage.run(&mut larger_world);
+
+        assert_eq!(0, larger_world.query::<&Synced>().iter(&larger_world).count());
+    }
+
+    #[test]
+    fn deferred_despawn_frame(){
+        let mut world = RollbackWorld::default();
+        let mut rollback_schedule = RollbackSchedule::default();
+        let mut generator = SpawnKeyGenerator::default();
+
+        world
+            .spawn()
+            .insert(generator.next_key("spawner"));
+        rollback_schedule.add_system_to_stage(RollbackScheduleStage::Update, (|mut commands: Commands, frame: Res<RollbackFrame>, spawned: Query<Entity, With<SpawnKey>>|{
+            if frame.frame == 1{
+                for entity in spawned.iter(){
+                    commands.entity(entity).despawn();
+                }
+            }
+        }).system());
+
+        let mut larger_world = World::default();
+        larger_world.insert_resource(world);
+        larger_world.insert_resource(RollbackBuffer::with_capacity(10));
+        larger_world.insert_resource(rollback_schedule);
+        larger_world.insert_resource(RollbackRegistry::default());
+        larger_world.insert_resource(SyncSettings{defer_despawn: true});
+        larger_world.insert_resource(Events::<SyncedDespawnEvent>::default());
+        larger_world.insert_resource(SyncedEntityMap::default());
+
+        let mut sync_stage = SystemStage::single_threaded();
+        sync_stage.add_system(sync_rollback_entities.system());
+        let mut rollback_stage = SystemStage::single_threaded();
+        rollback_stage.add_system(rollback_system.system());
+
+        sync_stage.run(&mut larger_world);
+        // Three ticks in one render frame, the target vanishes in the middle one.
+        for _ in 0..3{
+            rollback_stage.run(&mut larger_world);
+        }
+        sync_stage.run(&mut larger_world);
+
+        let frames = larger_world
+            .query::<&PendingDespawn>()
+            .iter(&larger_world)
+            .map(|pending| pending.frame)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![1], frames);
+    }
+
+    #[test]
+    fn hierarchy_clone(){
+        let mut world = RollbackWorld::default();
+        let registry = RollbackRegistry::default();
+
+        let parent = world.spawn().id();
+        let child = world
+            .spawn()
+            .insert(Parent(parent))
+            .id();
+        world.entity_mut(parent).insert(Children::with(&[child]));
+
+        let mut other_world = RollbackWorld::default();
+        for _ in 0..4{
+            other_world.spawn().insert(0usize);
+        }
+
+        overwrite_world(&world, &mut other_world, &registry).unwrap();
+
+        let (new_parent, children) = other_world.query::<(Entity, &Children)>().iter(&other_world).next().unwrap();
+        let new_child = children[0];
+        assert_eq!(new_parent, other_world.get::<Parent>(new_child).unwrap().0);
+    }
+
+    #[test]
+    fn hierarchy_sync(){
+        let mut world = RollbackWorld::default();
+        let parent = world.spawn().id();
+        let child = world
+            .spawn()
+            .insert(Parent(parent))
+            .id();
+        world.entity_mut(parent).insert(Children::with(&[child]));
+
+        let mut larger_world = World::default();
+        larger_world.insert_resource(world);
+        larger_world.insert_resource(RollbackBuffer::with_capacity(10));
+        larger_world.insert_resource(SyncSettings::default());
+        larger_world.insert_resource(Events::<SyncedDespawnEvent>::default());
+        larger_world.insert_resource(SyncedEntityMap::default());
+
+        let mut sync_stage = SystemStage::single_threaded();
+        sync_stage.add_system(sync_rollback_entities.system());
+        let mut post_update_stage = SystemStage::single_threaded();
+        post_update_stage.add_system(sync_rollback_hierarchy.exclusive_system().at_start());
+        post_update_stage.add_system(parent_update_system.system());
+
+        sync_stage.run(&mut larger_world);
+        post_update_stage.run(&mut larger_world);
+
+        let synced_entity_map = larger_world.get_resource::<SyncedEntityMap>().unwrap().clone();
+        let outer_parent = synced_entity_map.outer_entity(parent).unwrap();
+        let outer_child = synced_entity_map.outer_entity(child).unwrap();
+        assert_eq!(outer_parent, larger_world.get::<Parent>(outer_child).unwrap().0);
+        assert_eq!(&[outer_child], &**larger_world.get::<Children>(outer_parent).unwrap());
+
+        larger_world.get_resource_mut::<RollbackWorld>().unwrap().entity_mut(child).remove::<Parent>();
+        sync_stage.run(&mut larger_world);
+        post_update_stage.run(&mut larger_world);
+
+        assert!(larger_world.get::<Parent>(outer_child).is_none());
+        assert!(larger_world.get::<Children>(outer_parent).is_none_or(|children| children.is_empty()));
+    }
+
+    #[test]
+    fn non_rolling_survives(){
+        let mut world = RollbackWorld::default();
+        let mut registry = RollbackRegistry::default();
+        registry.register_non_rolling::<DebugLabel>();
+
+        let entity = world
+            .spawn()
+            .insert(1usize)
+            .insert(DebugLabel("spawned".to_string()))
+            .id();
+
+        let mut snapshot = clone_world(&world, &registry).unwrap();
+        assert_eq!(0, snapshot.query::<&DebugLabel>().iter(&snapshot).count());
+
+        *world.get_mut::<usize>(entity).unwrap() = 2;
+        world.get_mut::<DebugLabel>(entity).unwrap().0 = "player".to_string();
+
+        overwrite_world(&snapshot, &mut world, &registry).unwrap();
+
+        assert_eq!(1, *world.get::<usize>(entity).unwrap());
+        assert_eq!("player", world.get::<DebugLabel>(entity).unwrap().0);
+    }
+
+    struct DebugLabel(String);
+
+    #[test]
+    fn unregistered_policy(){
+        let mut world = RollbackWorld::default();
+        let mut registry = RollbackRegistry::default();
+
+        world
+            .spawn()
+            .insert(1usize)
+            .insert(DebugLabel("player".to_string()));
+        world.insert_resource(Incer{inc: 1});
+
+        assert!(clone_world(&world, &registry).is_err());
+
+        registry.set_unregistered_policy(UnregisteredPolicy::WarnOnceAndSkip);
+        let mut snapshot = clone_world(&world, &registry).unwrap();
+        assert_eq!(1, *snapshot.query::<&usize>().iter(&snapshot).next().unwrap());
+        assert!(snapshot.get_resource::<Incer>().is_none());
+
+        registry.set_unregistered_policy(UnregisteredPolicy::SilentlySkip);
+        overwrite_world(&snapshot, &mut world, &registry).unwrap();
+        assert!(world.get_resource::<Incer>().is_some());
+    }
+
+    #[test]
+    fn registry_audit(){
+        let mut world = RollbackWorld::default();
+        let registry = RollbackRegistry::default();
+
+        let labelled = world
+            .spawn()
+            .insert(1usize)
+            .insert(DebugLabel("player".to_string()))
+            .id();
+        world.spawn().insert(2usize);
+        world.insert_resource(Incer{inc: 1});
+
+        let audit = registry.audit(&world);
+        assert_eq!(1, audit.components.len());
+        assert!(audit.components[0].name.ends_with("DebugLabel"));
+        assert_eq!(vec![labelled], audit.components[0].entities);
+        assert_eq!(1, audit.resources.len());
+        assert!(audit.resources[0].name.ends_with("Incer"));
+    }
+
+    #[test]
+    fn resource_entity_mapping(){
+        let mut world = RollbackWorld::default();
+        let mut registry = RollbackRegistry::default();
+        registry.register_entity_mappable::<CurrentTarget>();
+
+        for _ in 0..4{
+            world.spawn();
+        }
+        let target = world
+            .spawn()
+            .insert(7usize)
+            .id();
+        world.insert_resource(CurrentTarget(target));
+
+        let snapshot = clone_world(&world, &registry).unwrap();
+        let snapshot_target = snapshot.get_resource::<CurrentTarget>().unwrap().0;
+        assert_eq!(7, *snapshot.get::<usize>(snapshot_target).unwrap());
+
+        world.despawn(target);
+        world.spawn().insert(8usize);
+
+        overwrite_world(&snapshot, &mut world, &registry).unwrap();
+        let restored_target = world.get_resource::<CurrentTarget>().unwrap().0;
+        assert_ne!(target, restored_target);
+        assert_eq!(7, *world.get::<usize>(restored_target).unwrap());
+
+        // A resource pointing at a despawned entity keeps pointing at it.
+        world.despawn(restored_target);
+        let snapshot = clone_world(&world, &registry).unwrap();
+        assert_eq!(restored_target, snapshot.get_resource::<CurrentTarget>().unwrap().0);
+
+        let mut rollback_schedule = RollbackSchedule::default();
+        rollback_schedule.add_system_to_stage(RollbackScheduleStage::Update, (|| {}).system());
+        let mut larger_world = World::default();
+        larger_world.insert_resource(world);
+        larger_world.insert_resource(RollbackBuffer::with_capacity(4));
+        larger_world.insert_resource(rollback_schedule);
+        larger_world.insert_resource(registry);
+
+        let mut helper_stage = SystemStage::single_threaded();
+        helper_stage.add_system(rollback_system.system());
+        for _ in 0..2{
+            helper_stage.run(&mut larger_world);
+        }
+        larger_world.get_resource_mut::<RollbackBuffer>().unwrap().add_overrides_relative(&1, Box::new(|| {}).system());
+        helper_stage.run(&mut larger_world);
+        assert!(larger_world.get_resource::<RollbackBuffer>().unwrap().get_world(1).is_some());
+    }
+
+    #[derive(Reflect, Serialize)]
+    struct CurrentTarget(Entity);
+
+    impl FromWorld for CurrentTarget{
+        fn from_world(_world: &mut World) -> Self{
+            CurrentTarget(Entity::new(u32::MAX))
+        }
+    }
+
+    impl MapEntities for CurrentTarget{
+        fn map_entities(&mut self, entity_map: &EntityMap) -> Result<(), MapEntitiesError>{
+            self.0 = entity_map.get(self.0)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn derive_registration(){
+        let mut world = RollbackWorld::default();
+        let mut registry = RollbackRegistry::default();
+        registry.register_all::<(Follower, Health)>();
+
+        let leader = world
+            .spawn()
+            .insert(Health(10))
+            .id();
+        world
+            .spawn()
+            .insert(Follower{target: Some(leader), distance: 3, cached: 9});
+
+        let mut snapshot = clone_world(&world, &registry).unwrap();
+        let follower = snapshot.query::<&Follower>().iter(&snapshot).next().unwrap();
+        assert_eq!(3, follower.distance);
+        assert_eq!(0, follower.cached);
+        assert_eq!(10, snapshot.get::<Health>(follower.target.unwrap()).unwrap().0);
+    }
+
+    #[test]
+    fn bevy_types_clone(){
+        let mut world = RollbackWorld::default();
+        let registry = RollbackRegistry::default();
+
+        world
+            .spawn()
+            .insert(Transform::from_xyz(1.0, 2.0, 3.0))
+            .insert(GlobalTransform::identity())
+            .insert(Timer::from_seconds(2.0, true))
+            .insert(Name::new("player"));
+
+        let mut snapshot = clone_world(&world, &registry).unwrap();
+        let (transform, timer, name) = snapshot.query::<(&Transform, &Timer, &Name)>().iter(&snapshot).next().unwrap();
+        assert_eq!(Vec3::new(1.0, 2.0, 3.0), transform.translation);
+        assert_eq!(2.0, timer.duration().as_secs_f32());
+        assert_eq!("player", name.as_str());
+    }
+
+    #[test]
+    fn clone_registration(){
+        let mut world = RollbackWorld::default();
+        world.spawn().insert(Cached{value: 5, scratch: 7});
+        world.insert_resource(Cached{value: 6, scratch: 8});
+
+        let mut reflect_registry = RollbackRegistry::default();
+        reflect_registry.register::<Cached>();
+        let mut clone_registry = RollbackRegistry::default();
+        clone_registry.register_clone::<Cached>();
+
+        // Both paths keep the reflected field and rebuild the skipped one with FromWorld.
+        for registry in [&reflect_registry, &clone_registry]{
+            let mut snapshot = clone_world(&world, registry).unwrap();
+            let cached = snapshot.query::<&Cached>().iter(&snapshot).next().unwrap();
+            assert_eq!((5, 0), (cached.value, cached.scratch));
+            let cached = snapshot.get_resource::<Cached>().unwrap();
+            assert_eq!((6, 0), (cached.value, cached.scratch));
+        }
+    }
+
+    #[derive(Rollback, Default, Clone)]
+    struct Cached{
+        value: usize,
+        #[rollback(skip)]
+        scratch: usize,
+    }
+
+    #[test]
+    fn clone_without_from_world(){
+        let mut world = RollbackWorld::default();
+        let mut registry = RollbackRegistry::default();
+        registry.register_all::<Armor>();
+
+        let entity = world
+            .spawn()
+            .insert(Armor(3))
+            .id();
+        world.insert_resource(Armor(4));
+
+        let snapshot = clone_world(&world, &registry).unwrap();
+        world.get_mut::<Armor>(entity).unwrap().0 = 0;
+        world.get_resource_mut::<Armor>().unwrap().0 = 0;
+
+        overwrite_world(&snapshot, &mut world, &registry).unwrap();
+        assert_eq!(3, world.get::<Armor>(entity).unwrap().0);
+        assert_eq!(4, world.get_resource::<Armor>().unwrap().0);
+
+        // Scenes only carry dynamic data, Armor is rebuilt from it without FromWorld.
+        let scene = scene_from_world(&world, &registry).unwrap();
+        assert!(scene.entities[0].components[0].any().downcast_ref::<Armor>().is_none());
+        let mut other_world = World::default();
+        let entity_map = spawn_scene(&scene, &mut other_world, &registry).unwrap();
+        assert_eq!(3, other_world.get::<Armor>(entity_map.get(entity).unwrap()).unwrap().0);
+
+        let type_registry = registry.registry.read();
+        let registration = type_registry.get(std::any::TypeId::of::<Armor>()).unwrap();
+        registration
+            .data::<ReflectResource>()
+            .unwrap()
+            .add_resource(&mut other_world, &*Armor(5).clone_value());
+        assert_eq!(5, other_world.get_resource::<Armor>().unwrap().0);
+        drop(type_registry);
+
+        let schema = registry.schema().unwrap();
+        let armor = schema.types.iter().find(|type_schema| type_schema.type_name.ends_with("Armor")).unwrap();
+        assert_eq!("tuple_struct", armor.kind);
+        assert_eq!("u32", armor.fields[0].type_name);
+    }
+
+    #[derive(Rollback, Clone)]
+    #[rollback(clone)]
+    struct Armor(u32);
+
+    #[test]
+    fn registry_fingerprint(){
+        let mut registry = RollbackRegistry::default();
+        let mut other_registry = RollbackRegistry::default();
+        registry.register::<Incer>();
+        other_registry.register::<Incer>();
+        assert_eq!(registry.fingerprint().unwrap(), other_registry.fingerprint().unwrap());
+
+        // Without #[derive(Rollback)] the fields of a type aren't known.
+        let schema = registry.schema().unwrap();
+        let incer = schema.types.iter().find(|type_schema| type_schema.type_name.ends_with("Incer")).unwrap();
+        assert_eq!("opaque", incer.kind);
+        assert!(incer.fields.is_empty());
+
+        other_registry.register_all::<(Follower, Health)>();
+        assert_ne!(registry.fingerprint().unwrap(), other_registry.fingerprint().unwrap());
+        let schema = other_registry.schema().unwrap();
+        let health = schema.types.iter().find(|type_schema| type_schema.type_name.ends_with("Health")).unwrap();
+        assert_eq!("0", health.fields[0].name);
+        assert_eq!("u32", health.fields[0].type_name);
+    }
+
+    #[test]
+    fn schema_without_instance(){
+        let mut registry = RollbackRegistry::default();
+        registry.register_all::<Score>();
+        let schema = registry.schema().unwrap();
+        let score = schema.types.iter().find(|type_schema| type_schema.type_name.ends_with("Score")).unwrap();
+        assert_eq!("points", score.fields[0].name);
+        assert_eq!("u32", score.fields[0].type_name);
+
+        // Describing a type never builds it, so a FromWorld that needs the world doesn't matter.
+        registry.register::<Bonus>();
+        let schema = registry.schema().unwrap();
+        let bonus = schema.types.iter().find(|type_schema| type_schema.type_name.ends_with("Bonus")).unwrap();
+        assert_eq!("opaque", bonus.kind);
+    }
+
+    struct ScoreConfig(u32);
+
+    // Neither can be built without a ScoreConfig in the world.
+    #[derive(Rollback)]
+    struct Score{
+        points: u32,
+    }
+
+    impl FromWorld for Score{
+        fn from_world(world: &mut World) -> Self{
+            Score{
+                points: world.get_resource::<ScoreConfig>().unwrap().0,
+            }
+        }
+    }
+
+    #[derive(Reflect)]
+    struct Bonus{
+        points: u32,
+    }
+
+    impl FromWorld for Bonus{
+        fn from_world(world: &mut World) -> Self{
+            Bonus{
+                points: world.get_resource::<ScoreConfig>().unwrap().0,
+            }
+        }
+    }
+
+    #[test]
+    fn restore_hooks(){
+        let mut world = RollbackWorld::default();
+        let mut registry = RollbackRegistry::default();
+        registry.register::<Health>();
+        registry.register_non_rolling::<HealthIndex>();
+        registry.add_snapshot_hook::<Health>(|world| world.insert_resource(HealthIndex(Vec::new())));
+        registry.add_restore_hook::<Health>(|world|{
+            let index = world
+                .query::<(Entity, &Health)>()
+                .iter(world)
+                .map(|(entity, _)| entity)
+                .collect();
+            world.insert_resource(HealthIndex(index));
+        });
+        registry.add_restored_hook(|world| world.get_resource_mut::<HealthIndex>().unwrap().0.sort());
+
+        let first = world
+            .spawn()
+            .insert(Health(1))
+            .id();
+        let second = world
+            .spawn()
+            .insert(Health(2))
+            .id();
+        world.insert_resource(HealthIndex(vec![first]));
+
+        let snapshot = clone_world(&world, &registry).unwrap();
+        assert!(snapshot.get_resource::<HealthIndex>().unwrap().0.is_empty());
+
+        world.despawn(second);
+        overwrite_world(&snapshot, &mut world, &registry).unwrap();
+        let mut expected = world
+            .query_filtered::<Entity, With<Health>>()
+            .iter(&world)
+            .collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(2, expected.len());
+        assert_eq!(expected, world.get_resource::<HealthIndex>().unwrap().0);
+    }
+
+    #[derive(Default)]
+    struct HealthIndex(Vec<Entity>);
+
+    #[test]
+    fn schema_migration(){
+        let mut registry = RollbackRegistry::default();
+        registry.register::<Stamina>();
+        registry.set_version::<Stamina>(1);
+        registry.add_migration::<Stamina>(0, |old|{
+            let old = match old.reflect_ref(){
+                ReflectRef::Struct(old) => old,
+                _ => panic!("Stamina should be a struct"),
+            };
+            let mut new = DynamicStruct::default();
+            new.set_name(old.type_name().to_owned());
+            new.insert_boxed("points", old.field("hp").unwrap().clone_value());
+            Box::new(new)
+        });
+
+        let old = ||{
+            let mut old = DynamicStruct::default();
+            old.set_name(std::any::type_name::<Stamina>().to_owned());
+            old.insert("hp", 7u32);
+            Box::new(old) as Box<dyn Reflect>
+        };
+        // Written before Stamina was versioned, so the schema it was saved with says version 0.
+        let saved_world = SavedWorld{
+            schema: RegistrySchema::default(),
+            scene: DynamicScene{
+                entities: vec![bevy::scene::Entity{
+                    entity: 0,
+                    components: vec![old()],
+                }],
+            },
+            resources: vec![old()],
+        };
+
+        let mut world = World::default();
+        load_world(saved_world, &mut world, &registry).unwrap();
+        assert_eq!(7, world.query::<&Stamina>().iter(&world).next().unwrap().points);
+        assert_eq!(7, world.get_resource::<Stamina>().unwrap().points);
+
+        // A save from the current build carries its version and loads without migrating.
+        let saved_world = save_world(&world, &registry).unwrap();
+        assert_eq!(1, saved_world.schema.version_of(std::any::type_name::<Stamina>()));
+        let mut other_world = World::default();
+        load_world(saved_world, &mut other_world, &registry).unwrap();
+        assert_eq!(7, other_world.get_resource::<Stamina>().unwrap().points);
+
+        registry.set_version::<Stamina>(2);
+        let saved_world = save_world(&world, &registry).unwrap();
+        registry.set_version::<Stamina>(3);
+        assert!(matches!(load_world(saved_world, &mut World::default(), &registry), Err(RollbackError::Migration(_))));
+    }
+
+    #[derive(Rollback, Default)]
+    struct Stamina{
+        points: u32,
+    }
+
+    #[test]
+    fn saved_world_ron(){
+        let mut registry = RollbackRegistry::default();
+        registry.register::<Stamina>();
+        registry.set_version::<Stamina>(1);
+
+        let mut world = World::default();
+        world.spawn().insert(Stamina{points: 3});
+        world.insert_resource(Stamina{points: 4});
+        let ron = save_world_ron(&world, &registry).unwrap();
+
+        // Stamina points are doubled in version 2, the save still holds version 1 values.
+        registry.set_version::<Stamina>(2);
+        registry.add_migration::<Stamina>(1, |old|{
+            let old = match old.reflect_ref(){
+                ReflectRef::Struct(old) => old,
+                _ => panic!("Stamina should be a struct"),
+            };
+            let points = *old.field("points").unwrap().downcast_ref::<u32>().unwrap();
+            let mut new = DynamicStruct::default();
+            new.set_name(old.type_name().to_owned());
+            new.insert("points", points * 2);
+            Box::new(new)
+        });
+
+        let saved_world = SavedWorld::deserialize_ron(&ron, &registry).unwrap();
+        assert_eq!(1, saved_world.schema.version_of(std::any::type_name::<Stamina>()));
+
+        let mut loaded = World::default();
+        load_world_ron(&ron, &mut loaded, &registry).unwrap();
+        assert_eq!(6, loaded.query::<&Stamina>().iter(&loaded).next().unwrap().points);
+        assert_eq!(8, loaded.get_resource::<Stamina>().unwrap().points);
+    }
+
+    #[test]
+    fn rollback_events(){
+        let mut world = RollbackWorld::default();
+        let rollback_buffer = RollbackBuffer::with_capacity(20);
+        let mut rollback_schedule = RollbackSchedule::default();
+        let mut registry = RollbackRegistry::default();
+
+        registry.register::<Pings>();
+        registry.register::<Pongs>();
+        registry.register_rollback_event::<Ping>();
+        registry.register_rollback_event::<Pong>();
+        world.insert_resource(Pings(0));
+        world.insert_resource(Pongs(0));
+        world.insert_resource(Events::<Ping>::default());
+        world.insert_resource(Events::<Pong>::default());
+
+        rollback_schedule.add_stage("test", SystemStage::single_threaded());
+        rollback_schedule.add_stage_after("test", "late", SystemStage::single_threaded());
+        rollback_schedule.add_system_to_stage("test", (|mut pings: EventWriter<Ping>| pings.send(Ping)).system().label("send"));
+        rollback_schedule.add_system_to_stage("test", (|mut reader: EventReader<Ping>, mut pings: ResMut<Pings>|{
+            pings.0 += reader.iter().count();
+        }).system().after("send"));
+        // Pongs sent late in a frame are read on the next one.
+        rollback_schedule.add_system_to_stage("test", (|mut reader: EventReader<Pong>, mut pongs: ResMut<Pongs>|{
+            pongs.0 += reader.iter().count();
+        }).system());
+        rollback_schedule.add_system_to_stage("late", (|mut pongs: EventWriter<Pong>| pongs.send(Pong)).system());
+
+        let mut larger_world = World::default();
+        larger_world.insert_resource(world);
+        larger_world.insert_resource(rollback_buffer);
+        larger_world.insert_resource(rollback_schedule);
+        larger_world.insert_resource(registry);
+
+        let mut helper_stage = SystemStage::single_threaded();
+        helper_stage.add_system(rollback_system.system());
+
+        for _ in 0..10{
+            helper_stage.run(&mut larger_world);
+        }
+        assert_eq!(10, larger_world.get_resource::<RollbackWorld>().unwrap().get_resource::<Pings>().unwrap().0);
+        assert_eq!(9, larger_world.get_resource::<RollbackWorld>().unwrap().get_resource::<Pongs>().unwrap().0);
+
+        larger_world.get_resource_mut::<RollbackBuffer>().unwrap().add_overrides_relative(&5, Box::new(|mut pings: EventWriter<Ping>, mut pongs: EventWriter<Pong>|{
+            pings.send(Ping);
+            pongs.send(Pong);
+        }).system());
+        helper_stage.run(&mut larger_world);
+
+        assert_eq!(12, larger_world.get_resource::<RollbackWorld>().unwrap().get_resource::<Pings>().unwrap().0);
+        assert_eq!(11, larger_world.get_resource::<RollbackWorld>().unwrap().get_resource::<Pongs>().unwrap().0);
+        let rollback_buffer = larger_world.get_resource::<RollbackBuffer>().unwrap();
+        assert_eq!(2, rollback_buffer.events::<Ping>(5).unwrap().len());
+        assert_eq!(1, rollback_buffer.events::<Ping>(6).unwrap().len());
+    }
+
+    #[derive(Clone, Debug)]
+    struct Ping;
+
+    #[derive(Clone, Debug)]
+    struct Pong;
+
+    #[derive(Rollback, Default)]
+    struct Pings(usize);
+
+    #[derive(Rollback, Default)]
+    struct Pongs(usize);
+
+    #[test]
+    fn side_effects_fire_once(){
+        let mut world = RollbackWorld::default();
+        let rollback_buffer = RollbackBuffer::with_capacity(20);
+        let mut rollback_schedule = RollbackSchedule::default();
+        let mut registry = RollbackRegistry::default();
+
+        registry.register::<Incer>();
+        world.insert_resource(Incer{inc: 1});
+
+        rollback_schedule.add_stage("test", SystemStage::single_threaded());
+        rollback_schedule.add_system_to_stage("test", (|mut emitter: ResMut<SideEffectEmitter>, incer: Res<Incer>|{
+            if incer.inc > 0{
+                emitter.emit(0, "boom");
+            }
+        }).system());
+
+        let mut larger_world = World::default();
+        larger_world.insert_resource(world);
+        larger_world.insert_resource(rollback_buffer);
+        larger_world.insert_resource(rollback_schedule);
+        larger_world.insert_resource(registry);
+        larger_world.insert_resource(SideEffectSettings::default());
+        larger_world.insert_resource(SideEffectLedger::default());
+        larger_world.insert_resource(Events::<SideEffectEvent>::default());
+
+        let mut helper_stage = SystemStage::single_threaded();
+        helper_stage.add_system(rollback_system.system().label("rollback"));
+        helper_stage.add_system(deliver_side_effects.system().after("rollback"));
+
+        let mut reader = larger_world.get_resource::<Events<SideEffectEvent>>().unwrap().get_reader();
+        for _ in 0..3{
+            helper_stage.run(&mut larger_world);
+        }
+        let events = reader.iter(larger_world.get_resource::<Events<SideEffectEvent>>().unwrap()).cloned().collect::<Vec<_>>();
+        assert_eq!(3, events.len());
+        assert!(events.iter().all(|event| matches!(event, SideEffectEvent::Fired(_))));
+
+        // Resimulating without changes doesn't repeat anything.
+        larger_world.get_resource_mut::<RollbackBuffer>().unwrap().add_overrides_relative(&2, Box::new(|| {}).system());
+        helper_stage.run(&mut larger_world);
+        let events = reader.iter(larger_world.get_resource::<Events<SideEffectEvent>>().unwrap()).cloned().collect::<Vec<_>>();
+        assert_eq!(vec![SideEffectEvent::Fired(SideEffectKey{frame: 3, source: 0, kind: "boom", index: 0})], events);
+
+        larger_world.get_resource_mut::<RollbackBuffer>().unwrap().add_overrides_relative(&2, Box::new(|mut incer: ResMut<Incer>|{
+            incer.inc = -1;
+        }).system());
+        helper_stage.run(&mut larger_world);
+        let events = reader.iter(larger_world.get_resource::<Events<SideEffectEvent>>().unwrap()).cloned().collect::<Vec<_>>();
+        assert_eq!(vec![
+            SideEffectEvent::Cancelled(SideEffectKey{frame: 2, source: 0, kind: "boom", index: 0}),
+            SideEffectEvent::Cancelled(SideEffectKey{frame: 3, source: 0, kind: "boom", index: 0}),
+        ], events);
+    }
+
+    #[test]
+    fn confirmed_side_effects(){
+        let mut world = RollbackWorld::default();
+        let mut rollback_schedule = RollbackSchedule::default();
+
+        rollback_schedule.add_stage("test", SystemStage::single_threaded());
+        rollback_schedule.add_system_to_stage("test", (|mut emitter: ResMut<SideEffectEmitter>|{
+            emitter.emit(0, "boom");
+        }).system());
+        world.insert_resource(SideEffectEmitter::default());
+
+        let mut larger_world = World::default();
+        larger_world.insert_resource(world);
+        larger_world.insert_resource(RollbackBuffer::with_capacity(20));
+        larger_world.insert_resource(rollback_schedule);
+        larger_world.insert_resource(RollbackRegistry::default());
+        larger_world.insert_resource(SideEffectSettings{confirmed_only: true});
+        larger_world.insert_resource(SideEffectLedger::default());
+        larger_world.insert_resource(Events::<SideEffectEvent>::default());
+
+        let mut helper_stage = SystemStage::single_threaded();
+        helper_stage.add_system(rollback_system.system().label("rollback"));
+        helper_stage.add_system(deliver_side_effects.system().after("rollback"));
+
+        let mut reader = larger_world.get_resource::<Events<SideEffectEvent>>().unwrap().get_reader();
+        for _ in 0..3{
+            helper_stage.run(&mut larger_world);
+        }
+        assert_eq!(0, reader.iter(larger_world.get_resource::<Events<SideEffectEvent>>().unwrap()).count());
+
+        // Effects fire once their frame is confirmed, long before it leaves the buffer.
+        larger_world.get_resource_mut::<RollbackBuffer>().unwrap().confirm_frame(1);
+        helper_stage.run(&mut larger_world);
+        let events = reader.iter(larger_world.get_resource::<Events<SideEffectEvent>>().unwrap()).cloned().collect::<Vec<_>>();
+        assert_eq!(vec![
+            SideEffectEvent::Fired(SideEffectKey{frame: 0, source: 0, kind: "boom", index: 0}),
+            SideEffectEvent::Fired(SideEffectKey{frame: 1, source: 0, kind: "boom", index: 0}),
+        ], events);
+    }
+
+    #[test]
+    fn default_schedule_layout(){
+        let mut world = World::default();
+        let mut rollback_schedule = RollbackSchedule::default();
+        world.insert_resource(Vec::<RollbackScheduleStage>::new());
+
+        rollback_schedule.add_system_to_stage(RollbackScheduleStage::Cleanup, (|mut ran: ResMut<Vec<RollbackScheduleStage>>| ran.push(RollbackScheduleStage::Cleanup)).system());
+        rollback_schedule.add_system_to_stage(RollbackScheduleStage::Update, (|mut ran: ResMut<Vec<RollbackScheduleStage>>| ran.push(RollbackScheduleStage::Update)).system());
+        rollback_schedule.add_system_to_stage(RollbackScheduleStage::Input, (|mut ran: ResMut<Vec<RollbackScheduleStage>>| ran.push(RollbackScheduleStage::Input)).system());
+        rollback_schedule.run_once(&mut world);
+
+        assert_eq!(
+            vec![RollbackScheduleStage::Input, RollbackScheduleStage::Update, RollbackScheduleStage::Cleanup],
+            *world.get_resource::<Vec<RollbackScheduleStage>>().unwrap()
+        );
+    }
+
+    #[test]
+    fn frame_run_criteria(){
+        let mut world = RollbackWorld::default();
+        let rollback_buffer = RollbackBuffer::with_capacity(20);
+        let mut rollback_schedule = RollbackSchedule::default();
+        let mut registry = RollbackRegistry::default();
+
+        registry.register_non_rolling::<RunCounts>();
+        world.insert_resource(RunCounts::default());
+
+        rollback_schedule.add_system_to_stage(RollbackScheduleStage::Update, (|mut counts: ResMut<RunCounts>| counts.new_frames += 1)
+            .system()
+            .with_run_criteria(on_new_frame()));
+        rollback_schedule.add_system_to_stage(RollbackScheduleStage::Update, (|mut counts: ResMut<RunCounts>| counts.resimulations += 1)
+            .system()
+            .with_run_criteria(on_resimulation()));
+        rollback_schedule.add_system_to_stage(RollbackScheduleStage::Update, (|mut counts: ResMut<RunCounts>| counts.confirmed += 1)
+            .system()
+            .with_run_criteria(on_confirmed_frame()));
+
+        let mut larger_world = World::default();
+        larger_world.insert_resource(world);
+        larger_world.insert_resource(rollback_buffer);
+        larger_world.insert_resource(rollback_schedule);
+        larger_world.insert_resource(registry);
+
+        let mut helper_stage = SystemStage::single_threaded();
+        helper_stage.add_system(rollback_system.system());
+
+        for _ in 0..3{
+            helper_stage.run(&mut larger_world);
+        }
+        larger_world.get_resource_mut::<RollbackBuffer>().unwrap().add_overrides_relative(&2, Box::new(|| {}).system());
+        helper_stage.run(&mut larger_world);
+        larger_world.get_resource_mut::<RollbackBuffer>().unwrap().confirm_frame(4);
+        helper_stage.run(&mut larger_world);
+
+        let counts = larger_world.get_resource::<RollbackWorld>().unwrap().get_resource::<RunCounts>().unwrap().clone();
+        assert_eq!(RunCounts{new_frames: 5, resimulations: 2, confirmed: 1}, counts);
+    }
+
+    #[test]
+    fn event_rollback_without_replay(){
+        let mut world = RollbackWorld::default();
+        let rollback_buffer = RollbackBuffer::with_capacity(20);
+        let mut rollback_schedule = RollbackSchedule::default();
+        let mut registry = RollbackRegistry::default();
+
+        registry.register::<Pings>();
+        registry.register_rollback_event::<Ping>();
+        registry.register_non_rolling::<RunCounts>();
+        world.insert_resource(Pings(0));
+        world.insert_resource(Events::<Ping>::default());
+        world.insert_resource(RunCounts::default());
+
+        rollback_schedule.add_system_to_stage(RollbackScheduleStage::Update, (|mut pings: EventWriter<Ping>| pings.send(Ping)).system().label("send"));
+        rollback_schedule.add_system_to_stage(RollbackScheduleStage::Update, (|mut reader: EventReader<Ping>, mut pings: ResMut<Pings>|{
+            pings.0 += reader.iter().count();
+        }).system().after("send"));
+        rollback_schedule.add_system_to_stage(RollbackScheduleStage::Update, (|mut counts: ResMut<RunCounts>| counts.new_frames += 1)
+            .system()
+            .with_run_criteria(on_new_frame()));
+        rollback_schedule.add_system_to_stage(RollbackScheduleStage::Update, (|mut counts: ResMut<RunCounts>| counts.resimulations += 1)
+            .system()
+            .with_run_criteria(on_resimulation()));
+
+        let mut larger_world = World::default();
+        larger_world.insert_resource(world);
+        larger_world.insert_resource(rollback_buffer);
+        larger_world.insert_resource(rollback_schedule);
+        larger_world.insert_resource(registry);
+
+        let mut helper_stage = SystemStage::single_threaded();
+        helper_stage.add_system(rollback_system.system());
+
+        for _ in 0..10{
+            helper_stage.run(&mut larger_world);
+        }
+        larger_world.get_resource_mut::<RollbackBuffer>().unwrap().add_overrides_relative(&3, Box::new(|| {}).system());
+        helper_stage.run(&mut larger_world);
+
+        // Only the three resimulated frames and the new one run, nothing is replayed to restore
+        // the event queues, and the pings of the frame before the restored one aren't read twice.
+        let rollback_world = larger_world.get_resource::<RollbackWorld>().unwrap();
+        assert_eq!(RunCounts{new_frames: 11, resimulations: 3, confirmed: 0}, *rollback_world.get_resource::<RunCounts>().unwrap());
+        assert_eq!(11, rollback_world.get_resource::<Pings>().unwrap().0);
+    }
+
+    #[derive(Default, Clone, Debug, PartialEq)]
+    struct RunCounts{
+        new_frames: usize,
+        resimulations: usize,
+        confirmed: usize,
+    }
+
+    #[test]
+    fn rollback_frame_resource(){
+        let mut world = RollbackWorld::default();
+        let rollback_buffer = RollbackBuffer::with_capacity(2);
+        let mut rollback_schedule = RollbackSchedule::default();
+        let mut registry = RollbackRegistry::default();
+
+        registry.register_non_rolling::<Vec<RollbackFrame>>();
+        world.insert_resource(Vec::<RollbackFrame>::new());
+        rollback_schedule.add_system_to_stage(RollbackScheduleStage::Update, (|mut seen: ResMut<Vec<RollbackFrame>>, frame: Res<RollbackFrame>| seen.push(*frame)).system());
+
+        let mut larger_world = World::default();
+        larger_world.insert_resource(world);
+        larger_world.insert_resource(rollback_buffer);
+        larger_world.insert_resource(rollback_schedule);
+        larger_world.insert_resource(registry);
+
+        let mut helper_stage = SystemStage::single_threaded();
+        helper_stage.add_system(rollback_system.system());
+
+        for _ in 0..4{
+            helper_stage.run(&mut larger_world);
+        }
+        larger_world.get_resource_mut::<RollbackBuffer>().unwrap().add_overrides_relative(&1, Box::new(|| {}).system());
+        helper_stage.run(&mut larger_world);
+
+        let rollback_world = larger_world.get_resource::<RollbackWorld>().unwrap();
+        let seen = rollback_world
+            .get_resource::<Vec<RollbackFrame>>()
+            .unwrap()
+            .iter()
+            .map(|frame| (frame.frame, frame.is_resimulation, frame.confirmed_frame))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![
+            (0, false, None),
+            (1, false, None),
+            (2, false, None),
+            (3, false, Some(0)),
+            (3, true, Some(1)),
+            (4, false, Some(1)),
+        ], seen);
+
+        let rollback_buffer = larger_world.get_resource::<RollbackBuffer>().unwrap();
+        assert!(rollback_buffer.get_world(4).unwrap().get_resource::<RollbackFrame>().is_none());
+    }
+
+    #[test]
+    fn rollback_control(){
+        let mut control = RollbackControl::default();
+        let mut clock = RollbackClock::default();
+        let tick_rate = RollbackTickRate{rate: 4.0};
+        let catch_up = CatchUpPolicy::default();
+
+        clock.advance(0.625, tick_rate, &mut control, &catch_up);
+        assert_eq!(2, clock.ticks());
+        clock.advance(0.125, tick_rate, &mut control, &catch_up);
+        assert_eq!(1, clock.ticks());
+
+        control.set_speed(0.5);
+        clock.advance(0.5, tick_rate, &mut control, &catch_up);
+        assert_eq!(1, clock.ticks());
+
+        // Steps asked for while running aren't taken once paused.
+        control.step(2);
+        control.pause();
+        clock.advance(1.0, tick_rate, &mut control, &catch_up);
+        assert_eq!(0, clock.ticks());
+        control.step(3);
+        clock.advance(1.0, tick_rate, &mut control, &catch_up);
+        assert_eq!(3, clock.ticks());
+        clock.advance(1.0, tick_rate, &mut control, &catch_up);
+        assert_eq!(0, clock.ticks());
+
+        control.resume();
+        control.set_speed(1.0);
+        clock.advance(0.25, tick_rate, &mut control, &catch_up);
+        assert_eq!(1, clock.ticks());
+
+        clock.advance(0.25, RollbackTickRate{rate: 8.0}, &mut control, &catch_up);
+        assert_eq!(2, clock.ticks());
+
+        let mut world = RollbackWorld::default();
+        world.insert_resource(RollbackTickRate{rate: 30.0});
+        let snapshot = clone_world(&world, &RollbackRegistry::default()).unwrap();
+        assert_eq!(30.0, snapshot.get_resource::<RollbackTickRate>().unwrap().rate);
+    }
+
+    // A rollback system counting up an isize by Incer every tick, for the catch up tests.
+    fn catch_up_world(catch_up: &CatchUpPolicy) -> (World, SystemStage){
+        let mut world = RollbackWorld::default();
+        let mut rollback_schedule = RollbackSchedule::default();
+        let mut registry = RollbackRegistry::default();
+        registry.register::<Incer>();
+        world.insert_resource(0isize);
+        world.insert_resource(Incer{inc: 1});
+        rollback_schedule.add_system_to_stage(RollbackScheduleStage::Update, (|mut current: ResMut<isize>, inc: Res<Incer>| *current += inc.inc).system());
+
+        let mut larger_world = World::default();
+        larger_world.insert_resource(world);
+        larger_world.insert_resource(RollbackBuffer::with_capacity(10));
+        larger_world.insert_resource(rollback_schedule);
+        larger_world.insert_resource(registry);
+        larger_world.insert_resource(catch_up.clone());
+        larger_world.insert_resource(Events::<RollbackFailed>::default());
+
+        let mut helper_stage = SystemStage::single_threaded();
+        helper_stage.add_system(rollback_system.system());
+        (larger_world, helper_stage)
+    }
+
+    // Runs as many ticks as a render frame of the given length gets at 4 ticks a second.
+    fn render_frame(larger_world: &mut World, helper_stage: &mut SystemStage, control: &mut RollbackControl, delta: f64){
+        let catch_up = larger_world.get_resource::<CatchUpPolicy>().unwrap().clone();
+        let mut clock = RollbackClock::default();
+        clock.advance(delta, RollbackTickRate{rate: 4.0}, control, &catch_up);
+        let ticks = clock.ticks();
+        larger_world.insert_resource(clock);
+        for _ in 0..ticks{
+            helper_stage.run(larger_world);
+        }
+    }
+
+    fn rolled_count(larger_world: &World) -> isize{
+        *larger_world.get_resource::<RollbackWorld>().unwrap().get_resource::<isize>().unwrap()
+    }
+
+    #[test]
+    fn catch_up_policy(){
+        let mut control = RollbackControl::default();
+        let mut clock = RollbackClock::default();
+        let tick_rate = RollbackTickRate{rate: 4.0};
+        let catch_up = CatchUpPolicy{
+            max_ticks: Some(2),
+            skip_intermediate_snapshots: true,
+        };
+        assert_eq!(Some(DroppedTicks{ticks: 2, seconds: 0.5}), clock.advance(1.0, tick_rate, &mut control, &catch_up));
+        assert_eq!(2, clock.ticks());
+
+        let (mut larger_world, mut helper_stage) = catch_up_world(&catch_up);
+        render_frame(&mut larger_world, &mut helper_stage, &mut control, 0.25);
+        render_frame(&mut larger_world, &mut helper_stage, &mut control, 0.5);
+        {
+            let rollback_buffer = larger_world.get_resource::<RollbackBuffer>().unwrap();
+            assert!(rollback_buffer.get_world(0).is_some());
+            assert!(rollback_buffer.get_world(1).is_none());
+            assert!(rollback_buffer.get_world(2).is_some());
+        }
+
+        // Frame 1 wasn't snapshotted, so this resimulates from frame 0.
+        larger_world.get_resource_mut::<RollbackBuffer>().unwrap().add_overrides_relative(&2, Box::new(|mut incer: ResMut<Incer>|{
+            incer.inc = -1;
+        }).system());
+        render_frame(&mut larger_world, &mut helper_stage, &mut control, 0.25);
+        assert_eq!(-2, rolled_count(&larger_world));
+    }
+
+    #[test]
+    fn catch_up_hitch(){
+        let mut control = RollbackControl::default();
+        let catch_up = CatchUpPolicy{
+            max_ticks: None,
+            skip_intermediate_snapshots: true,
+        };
+        let (mut larger_world, mut helper_stage) = catch_up_world(&catch_up);
+
+        // A hitch twice as long as the buffer still leaves snapshots inside it.
+        render_frame(&mut larger_world, &mut helper_stage, &mut control, 5.0);
+        assert_eq!(20, rolled_count(&larger_world));
+
+        larger_world.get_resource_mut::<RollbackBuffer>().unwrap().add_overrides_relative(&2, Box::new(|mut incer: ResMut<Incer>|{
+            incer.inc = -1;
+        }).system());
+        render_frame(&mut larger_world, &mut helper_stage, &mut control, 0.25);
+        assert_eq!(15, rolled_count(&larger_world));
+        assert!(larger_world.get_resource::<Events<RollbackFailed>>().unwrap().iter_current_update_events().next().is_none());
+
+        // Rolling back past every snapshot left only runs the new frame, and says so.
+        larger_world.get_resource_mut::<RollbackBuffer>().unwrap().add_overrides_relative(&10, Box::new(|mut incer: ResMut<Incer>|{
+            incer.inc = 1;
+        }).system());
+        render_frame(&mut larger_world, &mut helper_stage, &mut control, 0.25);
+        assert_eq!(14, rolled_count(&larger_world));
+        let failures = larger_world.get_resource::<Events<RollbackFailed>>().unwrap();
+        let failure = failures.iter_current_update_events().next().unwrap();
+        assert_eq!(11, failure.frame);
+        assert!(matches!(failure.error, RollbackError::MissingSnapshot(11)));
+    }
+
+    #[test]
+    fn restart_match(){
+        let mut world = RollbackWorld::default();
+        let mut rollback_schedule = RollbackSchedule::default();
+        let mut rollback_startup_schedule = RollbackStartupSchedule::default();
+        let mut registry = RollbackRegistry::default();
+
+        // Inserted like AppBuilder::insert_rollback_resource does, before startup.
+        let mut initial_resources = InitialRollbackResources::default();
+        registry.register::<Incer>();
+        registry.register::<Pongs>();
+        registry.register_rollback_event::<Pong>();
+        world.insert_resource(RollbackTickRate{rate: 30.0});
+        world.insert_resource(Incer{inc: 3});
+        initial_resources.add::<Incer>();
+        world.insert_resource(Events::<Pong>::default());
+        rollback_schedule.add_stage_after(RollbackScheduleStage::Update, "late", SystemStage::single_threaded());
+        rollback_schedule.add_system_to_stage(RollbackScheduleStage::Update, (|mut commands: Commands, mut current: ResMut<isize>, mut incer: ResMut<Incer>|{
+            commands.spawn().insert(10usize);
+            *current += 1;
+            incer.inc += 1;
+        }).system());
+        rollback_schedule.add_system_to_stage(RollbackScheduleStage::Update, (|mut reader: EventReader<Pong>, mut pongs: ResMut<Pongs>|{
+            pongs.0 += reader.iter().count();
+        }).system());
+        rollback_schedule.add_system_to_stage("late", (|mut pongs: EventWriter<Pong>| pongs.send(Pong)).system());
+        rollback_startup_schedule.add_stage("startup", SystemStage::single_threaded());
+        rollback_startup_schedule.add_system_to_stage("startup", (|mut commands: Commands|{
+            commands.insert_resource(0isize);
+            commands.insert_resource(Pongs(0));
+        }).system());
+
+        let mut larger_world = World::default();
+        larger_world.insert_resource(world);
+        larger_world.insert_resource(RollbackBuffer::with_capacity(10));
+        larger_world.insert_resource(rollback_schedule);
+        larger_world.insert_resource(rollback_startup_schedule);
+        larger_world.insert_resource(registry);
+        larger_world.insert_resource(RollbackControl::default());
+        larger_world.insert_resource(initial_resources);
+
+        let mut startup_stage = SystemStage::single_threaded();
+        startup_stage.add_system(rollback_startup.system());
+        startup_stage.run(&mut larger_world);
+
+        let mut helper_stage = SystemStage::single_threaded();
+        helper_stage.add_system(rollback_system.system());
+        for _ in 0..5{
+            helper_stage.run(&mut larger_world);
+        }
+        assert_eq!(5, *larger_world.get_resource::<RollbackWorld>().unwrap().get_resource::<isize>().unwrap());
+
+        // Nothing happens until a restart is asked for.
+        restart_rollback(&mut larger_world);
+        assert_eq!(5, larger_world.get_resource::<RollbackBuffer>().unwrap().current_frame());
+
+        // Settings changed while playing carry over to the next match.
+        larger_world.get_resource_mut::<RollbackWorld>().unwrap().insert_resource(RollbackTickRate{rate: 45.0});
+        larger_world.get_resource_mut::<RollbackControl>().unwrap().restart();
+        restart_rollback(&mut larger_world);
+
+        let rollback_buffer = larger_world.get_resource::<RollbackBuffer>().unwrap();
+        assert_eq!(0, rollback_buffer.current_frame());
+        assert!(rollback_buffer.get_world(0).is_none());
+        let rollback_world = larger_world.get_resource::<RollbackWorld>().unwrap();
+        assert_eq!(0, *rollback_world.get_resource::<isize>().unwrap());
+        assert_eq!(45.0, rollback_world.get_resource::<RollbackTickRate>().unwrap().rate);
+        assert_eq!(3, rollback_world.get_resource::<Incer>().unwrap().inc);
+        assert_eq!(0, rollback_world.entities().len());
+
+        // The pong sent late in the last frame of the old match isn't read in the new one.
+        helper_stage.run(&mut larger_world);
+        let rollback_world = larger_world.get_resource::<RollbackWorld>().unwrap();
+        assert_eq!(1, *rollback_world.get_resource::<isize>().unwrap());
+        assert_eq!(0, rollback_world.get_resource::<Pongs>().unwrap().0);
+    }
+
+    #[derive(Rollback, Default)]
+    struct Follower{
+        #[rollback(entity)]
+        target: Option<Entity>,
+        distance: usize,
+        #[rollback(skip)]
+        cached: usize,
+    }
+
+    #[derive(Rollback, Default)]
+    struct Health(u32);
+
     #[derive(Default, Reflect, Serialize)]
     #[reflect(Component)]
     struct Incer{
@@ -4,6 +4,7 @@ use bevy::ecs::entity::MapEntitiesError;
 use bevy::ecs::component::Component;
 use bevy::prelude::*;
 use bevy::reflect::*;
+use crate::rollback_type::{FromReflect, SkippedFields};
 
 #[derive(Clone)]
 pub struct ReflectResource {
@@ -46,6 +47,38 @@ impl ReflectResource {
     }
 }
 
+impl ReflectResource {
+    /// Builds the resource functions from FromReflect instead of FromWorld, for types without a
+    /// sensible default.
+    pub fn from_reflect<C: Component + FromReflect>() -> Self {
+        ReflectResource {
+            add_resource: |world, reflected_resource| {
+                let resource = C::from_reflect(reflected_resource)
+                    .unwrap_or_else(|| panic!("{} can't be built from {}", std::any::type_name::<C>(), reflected_resource.type_name()));
+                world.insert_resource(resource);
+            },
+            remove_resource: |world| {
+                world.remove_resource::<C>();
+            },
+            apply_resource: |world, reflected_resource| {
+                let mut resource = world.get_resource_mut::<C>().unwrap();
+                resource.apply(reflected_resource);
+            },
+            copy_resource: |source_world, destination_world| {
+                let source_resource = source_world.get_resource::<C>().unwrap();
+                let resource = C::from_reflect(source_resource).unwrap();
+                destination_world
+                    .insert_resource(resource);
+            },
+            reflect_resource: |world| {
+                world
+                    .get_resource::<C>()
+                    .map(|c| c as &dyn Reflect)
+            },
+        }
+    }
+}
+
 impl<C: Component + Reflect + FromWorld> FromType<C> for ReflectResource {
     fn from_type() -> Self {
         ReflectResource {
@@ -104,4 +137,207 @@ impl<C: Component + MapEntities> FromType<C> for ReflectMapEntitiesResources {
             },
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Clone)]
+pub struct ReflectRemoveComponent {
+    remove_component: fn(&mut World, Entity),
+}
+
+impl ReflectRemoveComponent {
+    pub fn remove_component(&self, world: &mut World, entity: Entity) {
+        (self.remove_component)(world, entity);
+    }
+}
+
+impl<C: Component> FromType<C> for ReflectRemoveComponent {
+    fn from_type() -> Self {
+        ReflectRemoveComponent {
+            remove_component: |world, entity| {
+                world.entity_mut(entity).remove::<C>();
+            },
+        }
+    }
+}
+
+
+/// The component half of ReflectComponent for types built through FromReflect, which
+/// ReflectComponent can't describe since it needs FromWorld.
+#[derive(Clone)]
+pub struct ReflectFromReflectComponent {
+    add_component: fn(&mut World, Entity, &dyn Reflect),
+    apply_component: fn(&mut World, Entity, &dyn Reflect),
+    reflect_component: fn(&World, Entity) -> Option<&dyn Reflect>,
+    copy_component: fn(&World, &mut World, Entity, Entity),
+}
+
+impl ReflectFromReflectComponent {
+    pub fn add_component(&self, world: &mut World, entity: Entity, component: &dyn Reflect) {
+        (self.add_component)(world, entity, component);
+    }
+
+    pub fn apply_component(&self, world: &mut World, entity: Entity, component: &dyn Reflect) {
+        (self.apply_component)(world, entity, component);
+    }
+
+    pub fn reflect_component<'a>(
+        &self,
+        world: &'a World,
+        entity: Entity
+    ) -> Option<&'a dyn Reflect> {
+        (self.reflect_component)(world, entity)
+    }
+
+    pub fn copy_component(
+        &self,
+        source_world: &World,
+        destination_world: &mut World,
+        source_entity: Entity,
+        destination_entity: Entity,
+    ) {
+        (self.copy_component)(
+            source_world,
+            destination_world,
+            source_entity,
+            destination_entity,
+        );
+    }
+}
+
+impl<C: Component + FromReflect> FromType<C> for ReflectFromReflectComponent {
+    fn from_type() -> Self {
+        ReflectFromReflectComponent {
+            add_component: |world, entity, reflected_component| {
+                let component = C::from_reflect(reflected_component)
+                    .unwrap_or_else(|| panic!("{} can't be built from {}", std::any::type_name::<C>(), reflected_component.type_name()));
+                world.entity_mut(entity).insert(component);
+            },
+            apply_component: |world, entity, reflected_component| {
+                let mut component = world.get_mut::<C>(entity).unwrap();
+                component.apply(reflected_component);
+            },
+            reflect_component: |world, entity| {
+                world
+                    .get_entity(entity)?
+                    .get::<C>()
+                    .map(|c| c as &dyn Reflect)
+            },
+            copy_component: |source_world, destination_world, source_entity, destination_entity| {
+                let source_component = source_world.get::<C>(source_entity).unwrap();
+                let component = C::from_reflect(source_component).unwrap();
+                destination_world
+                    .entity_mut(destination_entity)
+                    .insert(component);
+            },
+        }
+    }
+}
+
+
+/// Type data for copying a type with Clone instead of rebuilding it field by field through reflection.
+/// Skipped fields are rebuilt with FromWorld after the copy, like the reflection path does.
+#[derive(Clone)]
+pub struct ReflectClone {
+    copy_component: fn(&World, &mut World, Entity, Entity),
+    copy_resource: fn(&World, &mut World),
+    skipped_fields: Option<ReflectSkippedFields>,
+}
+
+impl ReflectClone {
+    pub fn copy_component(
+        &self,
+        source_world: &World,
+        destination_world: &mut World,
+        source_entity: Entity,
+        destination_entity: Entity,
+    ) {
+        (self.copy_component)(
+            source_world,
+            destination_world,
+            source_entity,
+            destination_entity,
+        );
+        if let Some(skipped_fields) = &self.skipped_fields{
+            (skipped_fields.reset_component)(destination_world, destination_entity);
+        }
+    }
+
+    pub fn copy_resource(
+        &self,
+        source_world: &World,
+        destination_world: &mut World
+    ) {
+        (self.copy_resource)(
+            source_world,
+            destination_world,
+        );
+        if let Some(skipped_fields) = &self.skipped_fields{
+            (skipped_fields.reset_resource)(destination_world);
+        }
+    }
+
+    pub fn with_skipped_fields(mut self, skipped_fields: Option<ReflectSkippedFields>) -> Self {
+        self.skipped_fields = skipped_fields;
+        self
+    }
+}
+
+impl<C: Component + Clone> FromType<C> for ReflectClone {
+    fn from_type() -> Self {
+        ReflectClone {
+            skipped_fields: None,
+            copy_component: |source_world, destination_world, source_entity, destination_entity| {
+                let component = source_world.get::<C>(source_entity).unwrap().clone();
+                destination_world
+                    .entity_mut(destination_entity)
+                    .insert(component);
+            },
+            copy_resource: |source_world, destination_world| {
+                let resource = source_world.get_resource::<C>().unwrap().clone();
+                destination_world
+                    .insert_resource(resource);
+            },
+        }
+    }
+}
+
+
+/// Type data for types with #[rollback(skip)] fields, added by #[derive(Rollback)] to the type's
+/// registration.
+#[derive(Clone)]
+pub struct ReflectSkippedFields {
+    reset_component: fn(&mut World, Entity),
+    reset_resource: fn(&mut World),
+}
+
+impl<C: Component + FromWorld + SkippedFields> FromType<C> for ReflectSkippedFields {
+    fn from_type() -> Self {
+        ReflectSkippedFields {
+            reset_component: |world, entity| {
+                let fresh = C::from_world(world);
+                if let Some(mut component) = world.get_mut::<C>(entity){
+                    component.reset_skipped(fresh);
+                }
+            },
+            reset_resource: |world| {
+                let fresh = C::from_world(world);
+                if let Some(mut resource) = world.get_resource_mut::<C>(){
+                    resource.reset_skipped(fresh);
+                }
+            },
+        }
+    }
+}
+
+
+/// A callback run on a whole world around snapshots.
+pub type WorldHook = fn(&mut World);
+
+/// Type data holding the callbacks a registered type wants run around snapshots.
+#[derive(Clone, Default)]
+pub struct RollbackHooks {
+    /// Run on a freshly taken snapshot world.
+    pub on_snapshot: Option<WorldHook>,
+    /// Run on the live world after a snapshot has been restored into it.
+    pub on_restore: Option<WorldHook>,
+}
@@ -2,6 +2,8 @@ use crate::rollback_registry::RollbackRegistry;
 use std::collections::HashMap;
 use crate::err::RollbackError;
 use crate::util::clone_world;
+use crate::rollback_event::FrameEvents;
+use std::any::TypeId;
 use std::collections::VecDeque;
 use bevy::prelude::*;
 use bevy::scene::{
@@ -13,21 +15,28 @@ use ron::de::*;
 
 pub struct RollbackBuffer{
     buffer: Vec<Option<World>>,
+    events: Vec<Option<(usize, FrameEvents)>>,
     overrides: HashMap<isize, SystemStage>,
     current_frame: usize,
+    confirmed_frame: Option<usize>,
     rollback_needed: isize,
+    vanished: HashMap<Entity, usize>,
 }
 
 impl RollbackBuffer{
     pub fn with_capacity(capacity: usize) -> RollbackBuffer{
         let mut buf = RollbackBuffer{
             buffer: Vec::with_capacity(capacity),
+            events: Vec::with_capacity(capacity),
             overrides: HashMap::default(),
             current_frame: 0,
+            confirmed_frame: None,
             rollback_needed: 0,
+            vanished: HashMap::default(),
         };
         for _ in 0..capacity{
             buf.buffer.push(None);
+            buf.events.push(None);
         }
         buf
     }
@@ -49,6 +58,30 @@ impl RollbackBuffer{
         Ok(old_world)
     }
 
+    /// Drops the stored world for the given index instead of snapshotting it, a rollback to it
+    /// resimulates from an earlier snapshot instead.
+    pub(crate) fn skip_world(&mut self, index: usize){
+        let len = self.buffer.len();
+        self.buffer[index % len] = None;
+    }
+
+    /// The latest frame at or before the given index that still has a stored world.
+    pub fn latest_world_before(&self, index: usize) -> Option<usize>{
+        (0..=index)
+            .rev()
+            .take_while(|frame| self.current_frame - frame <= self.buffer.len())
+            .find(|frame| self.get_world(*frame).is_some())
+    }
+
+    /// Whether the given frame has to be snapshotted even when skipping snapshots, because the
+    /// latest stored world is half the buffer old and would otherwise leave the window.
+    pub(crate) fn snapshot_due(&self, index: usize) -> bool{
+        index
+            .checked_sub(1)
+            .and_then(|previous| self.latest_world_before(previous))
+            .is_none_or(|latest| index - latest >= (self.buffer.len() / 2).max(1))
+    }
+
     /// Gets the world at the given index without changing the internal buffer.
     pub fn get_world(&self, index: usize) -> Option<&World>{
         if self.current_frame - index > self.buffer.len(){
@@ -73,6 +106,30 @@ impl RollbackBuffer{
             .as_mut()
     }
 
+    pub(crate) fn push_events(&mut self, index: usize, events: FrameEvents){
+        let len = self.events.len();
+        self.events[index % len] = Some((index, events));
+    }
+
+    /// Gets every rollback event sent while simulating the given frame.
+    pub(crate) fn frame_events(&self, index: usize) -> Option<&FrameEvents>{
+        self
+            .events
+            .get(index % self.events.len())
+            .and_then(|events| events.as_ref())
+            .filter(|(frame, _)| *frame == index)
+            .map(|(_, events)| events)
+    }
+
+    /// Gets the rollback events of the given type sent while simulating the given frame.
+    pub fn events<T: 'static>(&self, index: usize) -> Option<&[T]>{
+        self
+            .frame_events(index)
+            .and_then(|events| events.get(&TypeId::of::<T>()))
+            .and_then(|events| events.downcast_ref::<Vec<T>>())
+            .map(|events| events.as_slice())
+    }
+
     pub fn add_overrides_relative(&mut self, index: &isize, overrides: impl System<In = (), Out = ()>){
         self
             .overrides
@@ -92,14 +149,53 @@ impl RollbackBuffer{
         self.overrides.get_mut(&index)
     }
 
+    /// The number of frames kept in the buffer, and so the furthest back a rollback can go.
+    pub fn capacity(&self) -> usize{
+        self.buffer.len()
+    }
+
     pub fn current_frame(&self) -> usize{
         self.current_frame
     }
 
+    /// Marks every frame up to the given one as confirmed, they are never expected to roll back again.
+    pub fn confirm_frame(&mut self, frame: usize){
+        self.confirmed_frame = self.confirmed_frame.max(Some(frame));
+    }
+
+    /// The latest confirmed frame, frames that have left the buffer are always confirmed.
+    pub fn confirmed_frame(&self) -> Option<usize>{
+        self.confirmed_frame.max(self.current_frame.checked_sub(self.buffer.len() + 1))
+    }
+
     pub fn rollback_needed(&self) -> isize{
         self.rollback_needed
     }
 
+    /// Forgets every stored world, event and override and starts counting frames from 0 again.
+    pub(crate) fn reset(&mut self){
+        for world in self.buffer.iter_mut(){
+            *world = None;
+        }
+        for events in self.events.iter_mut(){
+            *events = None;
+        }
+        self.overrides.clear();
+        self.current_frame = 0;
+        self.confirmed_frame = None;
+        self.rollback_needed = 0;
+        self.vanished.clear();
+    }
+
+    /// Remembers the frame a synced rollback entity was first seen gone after.
+    pub(crate) fn record_vanished(&mut self, entity: Entity, frame: usize){
+        self.vanished.entry(entity).or_insert(frame);
+    }
+
+    pub(crate) fn take_vanished(&mut self, entity: Entity) -> Option<usize>{
+        self.vanished.remove(&entity)
+    }
+
     pub(crate) fn inc_frame(&mut self){
         self.current_frame += 1;
     }
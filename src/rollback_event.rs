@@ -0,0 +1,99 @@
+use bevy::prelude::*;
+use bevy::ecs::component::Component;
+use bevy::app::Events;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+/// The events of every rollback event type sent while simulating a single frame.
+pub type FrameEvents = HashMap<TypeId, Box<dyn Any + Send + Sync>>;
+
+/// An event type living in the RollbackWorld. Its queue is updated once before every simulated
+/// frame like Bevy does, so readers see the events of the previous frame as well as their own.
+/// The events sent during a frame are recorded after it, and put back in the queue on rollback.
+///
+/// Readers keep their place in a queue inside their systems, as the id of the next event to read,
+/// and that place can't be rolled back. So the restored events are given the ids of the latest
+/// ones instead: a reader that had left the last n events of the latest frame unread sees the
+/// last n events of the frame before the restored one, exactly as it would have if both frames
+/// sent as many events after it ran.
+#[derive(Clone, Copy)]
+pub(crate) struct RollbackEvent{
+    pub(crate) type_id: TypeId,
+    update: fn(&mut World),
+    record: fn(&World) -> Option<Box<dyn Any + Send + Sync>>,
+    restore: fn(&mut World, Option<&(dyn Any + Send + Sync)>),
+}
+
+/// A second queue for an event type, kept at the event count the live queue had when it was last
+/// updated. Event ids only ever grow, so this is where a restored queue with lower ids starts from.
+pub(crate) struct SpareEvents<T>(Events<T>);
+
+impl<T: Component> Default for SpareEvents<T>{
+    fn default() -> Self{
+        SpareEvents(Events::default())
+    }
+}
+
+impl RollbackEvent{
+    pub(crate) fn of<T: Component + Clone>() -> Self{
+        RollbackEvent{
+            type_id: TypeId::of::<T>(),
+            update: |world|{
+                if world.get_resource::<Events<T>>().is_none(){
+                    return;
+                }
+                world.get_resource_or_insert_with(SpareEvents::<T>::default);
+                world.resource_scope(|world, mut spare: Mut<SpareEvents<T>>|{
+                    let mut events = world.get_resource_mut::<Events<T>>().unwrap();
+                    spare.0.extend(events.iter_current_update_events().cloned());
+                    spare.0.clear();
+                    events.update();
+                });
+            },
+            record: |world|{
+                world
+                    .get_resource::<Events<T>>()
+                    .map(|events| Box::new(events
+                        .iter_current_update_events()
+                        .cloned()
+                        .collect::<Vec<T>>()) as Box<dyn Any + Send + Sync>)
+            },
+            restore: |world, recorded|{
+                if world.get_resource::<Events<T>>().is_none(){
+                    return;
+                }
+                world.get_resource_or_insert_with(SpareEvents::<T>::default);
+                world.resource_scope(|world, mut spare: Mut<SpareEvents<T>>|{
+                    let mut events = world.get_resource_mut::<Events<T>>().unwrap();
+                    let recorded = recorded
+                        .and_then(|recorded| recorded.downcast_ref::<Vec<T>>())
+                        .map(|recorded| recorded.as_slice())
+                        .unwrap_or(&[]);
+                    // The spare queue is at the count the latest frame started from, it's brought
+                    // up to where the restored events have to start so they end where the latest
+                    // frame's events did.
+                    let latest = events.iter_current_update_events().cloned().collect::<Vec<T>>();
+                    let kept = recorded.len().min(latest.len());
+                    spare.0.extend(latest[..latest.len() - kept].iter().cloned());
+                    spare.0.update();
+                    spare.0.update();
+                    spare.0.extend(recorded[recorded.len() - kept..].iter().cloned());
+                    std::mem::swap(&mut *events, &mut spare.0);
+                    spare.0.clear();
+                });
+            },
+        }
+    }
+
+    pub(crate) fn update(&self, world: &mut World){
+        (self.update)(world)
+    }
+
+    pub(crate) fn record(&self, world: &World) -> Option<Box<dyn Any + Send + Sync>>{
+        (self.record)(world)
+    }
+
+    pub(crate) fn restore(&self, world: &mut World, recorded: Option<&(dyn Any + Send + Sync)>){
+        (self.restore)(world, recorded)
+    }
+}
@@ -1,25 +1,97 @@
 use bevy::tasks::ComputeTaskPool;
+use bevy::transform::components::{Children, Parent, PreviousParent, Transform, GlobalTransform};
+use bevy::math::{Vec2, Vec3, Vec4, Quat};
+use bevy::core::{Timer, Name};
 use bevy::ecs::entity::MapEntities;
-use crate::reflect_resource::ReflectMapEntitiesResources;
-use crate::system::SyncedRollback;
+use bevy::ecs::archetype::ArchetypeId;
+use crate::reflect_resource::{ReflectMapEntitiesResources, ReflectRemoveComponent, ReflectClone, ReflectFromReflectComponent, ReflectSkippedFields, RollbackHooks, WorldHook};
+use crate::system::{SyncedRollback, SyncedEntityMap, mirror_component};
+use crate::rollback_type::{RollbackType, FromReflect};
+use crate::schema::{ReflectSchema, RegistrySchema, ReflectMigrations, Migration, SavedWorld, migrate_saved_world};
+use crate::util::SnapshotOf;
+use crate::spawn_key::{SpawnKey, SpawnKeyGenerator};
+use crate::rollback_event::{RollbackEvent, SpareEvents, FrameEvents};
+use crate::side_effect::SideEffectEmitter;
+use crate::RollbackFrame;
+use crate::control::RollbackTickRate;
+use bevy::app::Events;
 use bevy::ecs::reflect::ReflectMapEntities;
 use bevy::{
-    reflect::{
-        TypeRegistry, FromType, Reflect, GetTypeRegistration,
-        erased_serde::private::serde::Serialize},
+    reflect::{TypeRegistry, FromType, Reflect, GetTypeRegistration},
     ecs::reflect::ReflectComponent,
-    ecs::world::FromWorld,
+    ecs::world::{World, FromWorld},
+    ecs::component::Component,
 };
 use std::ops::{Deref, DerefMut};
-use std::collections::HashSet;
+use std::collections::{HashSet, HashMap};
+use std::fmt;
+use std::sync::Mutex;
+use crate::err::RollbackError;
+use bevy::log::warn;
 use std::any::{Any, TypeId};
 
 use crate::reflect_resource::ReflectResource;
+use bevy::ecs::entity::Entity;
+
+/// A type found in a world that the registry doesn't know how to roll back.
+#[derive(Debug, Clone)]
+pub struct UnregisteredType{
+    pub name: String,
+    pub archetypes: Vec<usize>,
+    pub entities: Vec<Entity>,
+}
+
+/// Every unregistered component and resource type in a world, see RollbackRegistry::audit.
+#[derive(Debug, Clone, Default)]
+pub struct RegistryAudit{
+    pub components: Vec<UnregisteredType>,
+    pub resources: Vec<UnregisteredType>,
+}
+
+impl RegistryAudit{
+    pub fn is_empty(&self) -> bool{
+        self.components.is_empty() && self.resources.is_empty()
+    }
+}
+
+impl fmt::Display for RegistryAudit{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        if self.is_empty(){
+            return writeln!(f, "Every type in the RollbackWorld is registered.");
+        }
+        writeln!(f, "The RollbackWorld contains types that aren't registered with the RollbackRegistry:")?;
+        for component in self.components.iter(){
+            writeln!(f, "  component {} (on {} entities in archetypes {:?})", component.name, component.entities.len(), component.archetypes)?;
+        }
+        for resource in self.resources.iter(){
+            writeln!(f, "  resource {}", resource.name)?;
+        }
+        write!(f, "Register them, or mark them with register_unreflectable or register_non_rolling.")
+    }
+}
+
+/// What snapshots do when they meet a type that isn't registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnregisteredPolicy{
+    /// Fail with RollbackError::UnregisteredType.
+    #[default]
+    Error,
+    /// Log a warning the first time each type is seen, and leave it out of snapshots.
+    WarnOnceAndSkip,
+    /// Leave the type out of snapshots without saying anything.
+    SilentlySkip,
+}
 
 /// A wrapped TypeRegistry with primatives preinserted and serializable.
 pub struct RollbackRegistry{
     pub(crate) registry: TypeRegistry,
     pub(crate) unregisterable: HashSet<TypeId>,
+    pub(crate) non_rolling: HashSet<TypeId>,
+    pub(crate) mirrored: Vec<fn(&mut World, &mut World)>,
+    pub(crate) unregistered_policy: UnregisteredPolicy,
+    pub(crate) on_restored: Vec<fn(&mut World)>,
+    pub(crate) events: Vec<RollbackEvent>,
+    warned: Mutex<HashSet<TypeId>>,
 }
 
 impl Default for RollbackRegistry{
@@ -27,27 +99,48 @@ impl Default for RollbackRegistry{
        let mut registry = RollbackRegistry{
            registry: TypeRegistry::default(),
            unregisterable: HashSet::default(),
+           non_rolling: HashSet::default(),
+           mirrored: Vec::default(),
+           unregistered_policy: UnregisteredPolicy::default(),
+           on_restored: Vec::default(),
+           events: Vec::default(),
+           warned: Mutex::default(),
         };
         
-        registry.register::<u8>();
-        registry.register::<bool>();
-        registry.register::<u16>();
-        registry.register::<u32>();
-        registry.register::<u64>();
-        registry.register::<u128>();
-        registry.register::<usize>();
-        registry.register::<i8>();
-        registry.register::<i16>();
-        registry.register::<i32>();
-        registry.register::<i64>();
-        registry.register::<i128>();
-        registry.register::<isize>();
-        registry.register::<f32>();
-        registry.register::<f64>();
-        registry.register::<String>();
+        registry.register_clone::<u8>();
+        registry.register_clone::<bool>();
+        registry.register_clone::<u16>();
+        registry.register_clone::<u32>();
+        registry.register_clone::<u64>();
+        registry.register_clone::<u128>();
+        registry.register_clone::<usize>();
+        registry.register_clone::<i8>();
+        registry.register_clone::<i16>();
+        registry.register_clone::<i32>();
+        registry.register_clone::<i64>();
+        registry.register_clone::<i128>();
+        registry.register_clone::<isize>();
+        registry.register_clone::<f32>();
+        registry.register_clone::<f64>();
+        registry.register_clone::<String>();
+        registry.register::<SpawnKey>();
+        registry.register_clone::<RollbackTickRate>();
+
+        // The hierarchy components carry ReflectMapEntities through their reflect attributes.
+        registry.register::<Parent>();
+        registry.register::<PreviousParent>();
+        registry.register::<Children>();
+
+        #[cfg(feature = "bevy_types")]
+        registry.register_bevy_types();
 
         registry.register_unreflectable::<ComputeTaskPool>();
         registry.register_unreflectable::<SyncedRollback>();
+        registry.register_unreflectable::<SpawnKeyGenerator>();
+        registry.register_unreflectable::<SideEffectEmitter>();
+        registry.register_unreflectable::<RollbackFrame>();
+        registry.register_unreflectable::<SnapshotOf>();
+        registry.register_non_rolling::<SyncedEntityMap>();
         
         registry
    } 
@@ -62,11 +155,13 @@ impl RollbackRegistry{
             .unwrap();
         registration.insert(<ReflectComponent as FromType<T>>::from_type());
         registration.insert(<ReflectResource as FromType<T>>::from_type());
+        registration.insert(<ReflectRemoveComponent as FromType<T>>::from_type());
+        registration.insert(ReflectSchema::opaque::<T>());
         drop(registry);
         self
     }
 
-    pub fn register_entity_mappable<T: Any + Reflect + GetTypeRegistration + FromWorld + Serialize + MapEntities>(&mut self) -> &mut Self{
+    pub fn register_entity_mappable<T: Any + Reflect + GetTypeRegistration + FromWorld + MapEntities>(&mut self) -> &mut Self{
         let mut registry = self.registry.write();
         registry.register::<T>();
         let registration = registry
@@ -74,14 +169,348 @@ impl RollbackRegistry{
             .unwrap();
         registration.insert(<ReflectComponent as FromType<T>>::from_type());
         registration.insert(<ReflectResource as FromType<T>>::from_type());
+        registration.insert(<ReflectRemoveComponent as FromType<T>>::from_type());
+        registration.insert(ReflectSchema::opaque::<T>());
+        registration.insert(<ReflectMapEntities as FromType<T>>::from_type());
+        registration.insert(<ReflectMapEntitiesResources as FromType<T>>::from_type());
+        drop(registry);
+        self
+    }
+
+    /// Registers a type that is snapshotted and restored with Clone, skipping the per field
+    /// reflection of the regular copy path. Fields marked #[rollback(skip)] are still rebuilt with
+    /// FromWorld, the same as through reflection.
+    pub fn register_clone<T: Any + Reflect + GetTypeRegistration + FromWorld + Clone>(&mut self) -> &mut Self{
+        self.register::<T>();
+        let mut registry = self.registry.write();
+        let registration = registry
+            .get_mut(std::any::TypeId::of::<T>())
+            .unwrap();
+        let skipped_fields = registration.data::<ReflectSkippedFields>().cloned();
+        registration.insert(<ReflectClone as FromType<T>>::from_type().with_skipped_fields(skipped_fields));
+        drop(registry);
+        self
+    }
+
+    /// Registers a type without FromWorld. It is snapshotted and restored through Clone, and rebuilt
+    /// purely from reflected data through FromReflect when it comes from a scene or a save.
+    pub fn register_reflect_clone<T: Component + GetTypeRegistration + Clone + FromReflect>(&mut self) -> &mut Self{
+        let mut registry = self.registry.write();
+        registry.register::<T>();
+        let registration = registry
+            .get_mut(std::any::TypeId::of::<T>())
+            .unwrap();
+        registration.insert(ReflectResource::from_reflect::<T>());
+        registration.insert(<ReflectFromReflectComponent as FromType<T>>::from_type());
+        registration.insert(<ReflectClone as FromType<T>>::from_type());
+        registration.insert(<ReflectRemoveComponent as FromType<T>>::from_type());
+        registration.insert(ReflectSchema::opaque::<T>());
+        drop(registry);
+        self
+    }
+
+    /// Adds entity mapping to a type that is already registered.
+    pub fn register_map_entities<T: Component + MapEntities>(&mut self) -> &mut Self{
+        let mut registry = self.registry.write();
+        let registration = registry
+            .get_mut(std::any::TypeId::of::<T>())
+            .expect("Register the type before adding entity mapping to it!");
         registration.insert(<ReflectMapEntities as FromType<T>>::from_type());
         registration.insert(<ReflectMapEntitiesResources as FromType<T>>::from_type());
         drop(registry);
         self
     }
 
+    /// Runs the hook on every new snapshot, after the world has been copied.
+    pub fn add_snapshot_hook<T: Any>(&mut self, hook: fn(&mut World)) -> &mut Self{
+        self.update_hooks::<T>(|hooks| hooks.on_snapshot = Some(hook));
+        self
+    }
+
+    /// Runs the hook on the live world after a snapshot has been restored, e.g. to rebuild
+    /// a spatial index from the restored components.
+    pub fn add_restore_hook<T: Any>(&mut self, hook: fn(&mut World)) -> &mut Self{
+        self.update_hooks::<T>(|hooks| hooks.on_restore = Some(hook));
+        self
+    }
+
+    /// Runs the hook on the live world after a restore, once every type's restore hook has run.
+    pub fn add_restored_hook(&mut self, hook: fn(&mut World)) -> &mut Self{
+        self.on_restored.push(hook);
+        self
+    }
+
+    fn update_hooks<T: Any>(&mut self, update: impl FnOnce(&mut RollbackHooks)){
+        let mut registry = self.registry.write();
+        let registration = registry
+            .get_mut(TypeId::of::<T>())
+            .expect("Register the type before adding hooks to it!");
+        let mut hooks = registration
+            .data::<RollbackHooks>()
+            .cloned()
+            .unwrap_or_default();
+        update(&mut hooks);
+        registration.insert(hooks);
+    }
+
+    /// Every per type hook picked out by the selector, ordered by type name.
+    pub(crate) fn type_hooks(&self, selector: fn(&RollbackHooks) -> Option<WorldHook>) -> Vec<WorldHook>{
+        let type_registry = self.registry.read();
+        let mut hooks = type_registry
+            .iter()
+            .filter_map(|registration| registration
+                .data::<RollbackHooks>()
+                .and_then(selector)
+                .map(|hook| (registration.name(), hook)))
+            .collect::<Vec<_>>();
+        hooks.sort_by(|a, b| a.0.cmp(b.0));
+        hooks.into_iter().map(|(_, hook)| hook).collect()
+    }
+
+    pub fn unregistered_policy(&self) -> UnregisteredPolicy{
+        self.unregistered_policy
+    }
+
+    pub fn set_unregistered_policy(&mut self, policy: UnregisteredPolicy) -> &mut Self{
+        self.unregistered_policy = policy;
+        self
+    }
+
+    /// Applies the unregistered policy to a type the snapshot functions couldn't find a registration for.
+    pub(crate) fn handle_unregistered(&self, type_id: TypeId, name: &str) -> Result<(), RollbackError>{
+        if self.unregisterable.contains(&type_id){
+            return Ok(());
+        }
+        match self.unregistered_policy{
+            UnregisteredPolicy::Error => Err(RollbackError::UnregisteredType(name.to_owned())),
+            UnregisteredPolicy::WarnOnceAndSkip => {
+                if self.warned.lock().unwrap().insert(type_id){
+                    warn!("{} isn't registered with the RollbackRegistry and will not be rolled back", name);
+                }
+                Ok(())
+            },
+            UnregisteredPolicy::SilentlySkip => Ok(()),
+        }
+    }
+
     pub fn register_unreflectable<T: Any>(&mut self) -> &mut Self{
         self.unregisterable.insert(std::any::TypeId::of::<T>());
         self
     }
+
+    /// Registers a local-only type. It is never copied into a snapshot, and when a snapshot is
+    /// restored it stays in place on the live entity (or in the live world for resources).
+    pub fn register_non_rolling<T: Any>(&mut self) -> &mut Self{
+        self.non_rolling.insert(std::any::TypeId::of::<T>());
+        self
+    }
+
+    /// Registers an event type used inside the RollbackWorld. The Events resource itself is kept
+    /// out of snapshots, its queue is recorded after every frame and restored on rollback instead.
+    pub fn register_rollback_event<T: Component + Clone>(&mut self) -> &mut Self{
+        self.register_non_rolling::<Events<T>>();
+        self.register_non_rolling::<SpareEvents<T>>();
+        if !self.events.iter().any(|event| event.type_id == TypeId::of::<T>()){
+            self.events.push(RollbackEvent::of::<T>());
+        }
+        self
+    }
+
+    /// Updates the queue of every rollback event type, called before a frame is simulated.
+    pub(crate) fn update_events(&self, world: &mut World){
+        for event in self.events.iter(){
+            event.update(world);
+        }
+    }
+
+    /// Puts the events recorded for the frame before a restored one back in the queue of every
+    /// rollback event type, or empties it without them.
+    pub(crate) fn restore_events(&self, world: &mut World, recorded: Option<&FrameEvents>){
+        for event in self.events.iter(){
+            let events = recorded
+                .and_then(|recorded| recorded.get(&event.type_id))
+                .map(|events| events.as_ref());
+            event.restore(world, events);
+        }
+    }
+
+    /// Drops the events left in the queue of every rollback event type, for a restart.
+    pub(crate) fn clear_events(&self, world: &mut World){
+        // Updating twice drops both buffers without moving the ids readers are at back.
+        self.update_events(world);
+        self.update_events(world);
+    }
+
+    /// Copies out the events sent during the frame that was just simulated.
+    pub(crate) fn record_events(&self, world: &World) -> FrameEvents{
+        self.events
+            .iter()
+            .filter_map(|event| event
+                .record(world)
+                .map(|recorded| (event.type_id, recorded)))
+            .collect()
+    }
+
+    /// Registers the Bevy math, transform and core types most games roll back.
+    pub fn register_bevy_types(&mut self) -> &mut Self{
+        self.register_clone::<Vec2>();
+        self.register_clone::<Vec3>();
+        self.register_clone::<Vec4>();
+        self.register_clone::<Quat>();
+        self.register_clone::<Transform>();
+        self.register_clone::<GlobalTransform>();
+        self.register_clone::<Timer>();
+        self.register_clone::<Name>();
+        self
+    }
+
+    /// Copies the component from every synced rollback entity onto its outer entity after each sync.
+    pub fn register_mirrored<T: Component + Clone>(&mut self) -> &mut Self{
+        self.mirrored.push(mirror_component::<T>);
+        self
+    }
+
+    /// Finds every component and resource in the world that a snapshot would fail on, instead of
+    /// stopping at the first one like clone_rollback_world_entities does.
+    pub fn audit(&self, world: &World) -> RegistryAudit{
+        let type_registry = self.registry.read();
+        let is_registered = |type_id: Option<TypeId>, resource: bool| match type_id{
+            Some(type_id) => self.non_rolling.contains(&type_id)
+                || self.unregisterable.contains(&type_id)
+                || type_registry
+                    .get(type_id)
+                    .map(|registration| registration.data::<ReflectClone>().is_some()
+                        || (!resource && registration.data::<ReflectComponent>().is_some())
+                        || (resource && registration.data::<ReflectResource>().is_some()))
+                    .unwrap_or(false),
+            None => false,
+        };
+
+        let mut components = HashMap::<_, UnregisteredType>::new();
+        for archetype in world.archetypes().iter(){
+            // Resources live in an archetype of their own, they are audited below.
+            if archetype.id() == ArchetypeId::resource(){
+                continue;
+            }
+            for component_id in archetype.components(){
+                let info = world.components().get_info(component_id).unwrap();
+                if is_registered(info.type_id(), false){
+                    continue;
+                }
+                let unregistered = components
+                    .entry(component_id)
+                    .or_insert_with(|| UnregisteredType{
+                        name: info.name().to_owned(),
+                        archetypes: Vec::new(),
+                        entities: Vec::new(),
+                    });
+                unregistered.archetypes.push(archetype.id().index());
+                unregistered.entities.extend(archetype.entities().iter().cloned());
+            }
+        }
+
+        let mut resources = Vec::new();
+        for component_id in world.archetypes().resource().unique_components().indices(){
+            let info = world.components().get_info(component_id).unwrap();
+            if !is_registered(info.type_id(), true){
+                resources.push(UnregisteredType{
+                    name: info.name().to_owned(),
+                    archetypes: Vec::new(),
+                    entities: Vec::new(),
+                });
+            }
+        }
+
+        let mut components = components.into_values().collect::<Vec<_>>();
+        components.sort_by(|a, b| a.name.cmp(&b.name));
+        resources.sort_by(|a, b| a.name.cmp(&b.name));
+        RegistryAudit{
+            components,
+            resources,
+        }
+    }
+
+    /// Describes every rolled back type, sorted by name so two builds with the same registrations
+    /// produce the same schema.
+    pub fn schema(&self) -> Result<RegistrySchema, RollbackError>{
+        let type_registry = self.registry.read();
+        let mut types = type_registry
+            .iter()
+            .filter(|registration| !self.non_rolling.contains(&(*registration).type_id()))
+            .filter_map(|registration| registration
+                .data::<ReflectSchema>()
+                .map(|reflect_schema| {
+                    let mut type_schema = reflect_schema.describe()?;
+                    type_schema.entity_mappable = registration.data::<ReflectMapEntities>().is_some();
+                    type_schema.version = registration
+                        .data::<ReflectMigrations>()
+                        .map(|migrations| migrations.version())
+                        .unwrap_or(0);
+                    Ok(type_schema)
+                }))
+            .collect::<Result<Vec<_>, RollbackError>>()?;
+        types.sort_by(|a, b| a.type_name.cmp(&b.type_name));
+        Ok(RegistrySchema{
+            types,
+        })
+    }
+
+    /// A stable hash of the schema, to compare against a peer, replay or save file before exchanging state.
+    pub fn fingerprint(&self) -> Result<u64, RollbackError>{
+        Ok(self.schema()?.fingerprint())
+    }
+
+    /// Replaces how a registered type is described in the schema.
+    pub fn set_schema<T: Any>(&mut self, schema: ReflectSchema) -> &mut Self{
+        self
+            .registry
+            .write()
+            .get_mut(TypeId::of::<T>())
+            .expect("Register the type before describing it!")
+            .insert(schema);
+        self
+    }
+
+    /// Sets the current version of a registered type. Bump it whenever the type's fields change,
+    /// and add a migration from the previous version.
+    pub fn set_version<T: Any>(&mut self, version: u32) -> &mut Self{
+        self.update_migrations::<T>(|migrations| migrations.set_version(version));
+        self
+    }
+
+    /// Adds a migration taking reflected data saved with `from_version` to `from_version + 1`.
+    pub fn add_migration<T: Any>(&mut self, from_version: u32, migration: Migration) -> &mut Self{
+        self.update_migrations::<T>(|migrations| migrations.add_migration(from_version, migration));
+        self
+    }
+
+    fn update_migrations<T: Any>(&mut self, update: impl FnOnce(&mut ReflectMigrations)){
+        let mut registry = self.registry.write();
+        let registration = registry
+            .get_mut(TypeId::of::<T>())
+            .expect("Register the type before versioning it!");
+        let mut migrations = registration
+            .data::<ReflectMigrations>()
+            .cloned()
+            .unwrap_or_default();
+        update(&mut migrations);
+        registration.insert(migrations);
+    }
+
+    /// Migrates a world deserialized from a save or replay to the current versions of its types,
+    /// using the schema it was saved with. load_world runs it before spawning anything.
+    pub fn migrate(&self, saved_world: &mut SavedWorld) -> Result<(), RollbackError>{
+        let type_registry = self.registry.read();
+        migrate_saved_world(saved_world, |type_name| type_registry
+            .get_with_name(type_name)
+            .and_then(|registration| registration.data::<ReflectMigrations>())
+            .cloned())
+    }
+
+    /// Registers a type deriving Rollback, or every type in a tuple of them, with whatever their
+    /// attributes ask for.
+    pub fn register_all<T: RollbackType>(&mut self) -> &mut Self{
+        T::register_rollback(self);
+        self
+    }
 }
\ No newline at end of file
@@ -1,11 +1,44 @@
 use bevy::prelude::*;
 use std::ops::{Deref, DerefMut};
 
-#[derive(Default)]
+/// The stages every RollbackSchedule starts with, run in this order on every simulated frame.
+#[derive(StageLabel, PartialEq, Eq, Hash, Clone, Debug)]
+pub enum RollbackScheduleStage{
+    Input,
+    PreUpdate,
+    Update,
+    PostUpdate,
+    Cleanup,
+}
+
 pub struct RollbackSchedule{
     schedule: Schedule
 }
 
+impl RollbackSchedule{
+    /// A schedule without any stages, for laying them out by hand.
+    pub fn empty() -> Self{
+        RollbackSchedule{
+            schedule: Schedule::default(),
+        }
+    }
+}
+
+impl Default for RollbackSchedule{
+    fn default() -> Self{
+        let mut schedule = Schedule::default();
+        schedule
+            .add_stage(RollbackScheduleStage::Input, SystemStage::parallel())
+            .add_stage(RollbackScheduleStage::PreUpdate, SystemStage::parallel())
+            .add_stage(RollbackScheduleStage::Update, SystemStage::parallel())
+            .add_stage(RollbackScheduleStage::PostUpdate, SystemStage::parallel())
+            .add_stage(RollbackScheduleStage::Cleanup, SystemStage::parallel());
+        RollbackSchedule{
+            schedule,
+        }
+    }
+}
+
 impl Deref for RollbackSchedule{
     type Target = Schedule;
 
@@ -0,0 +1,111 @@
+use crate::rollback_registry::RollbackRegistry;
+use bevy::ecs::entity::{Entity, EntityMap, MapEntitiesError};
+use bevy::math::{Vec2, Vec3, Vec4, Quat};
+use bevy::reflect::{Reflect, ReflectRef};
+
+/// A type that knows how to register itself with a RollbackRegistry, usually through #[derive(Rollback)].
+/// Tuples of RollbackTypes register every member, so a whole game can be registered at once with
+/// `registry.register_all::<(Health, Position, Target)>()`.
+pub trait RollbackType{
+    fn register_rollback(registry: &mut RollbackRegistry);
+}
+
+macro_rules! impl_rollback_type_tuple{
+    ($($name: ident),*) => {
+        impl<$($name: RollbackType),*> RollbackType for ($($name,)*){
+            fn register_rollback(registry: &mut RollbackRegistry){
+                $($name::register_rollback(registry);)*
+            }
+        }
+    }
+}
+
+impl_rollback_type_tuple!(A);
+impl_rollback_type_tuple!(A, B);
+impl_rollback_type_tuple!(A, B, C);
+impl_rollback_type_tuple!(A, B, C, D);
+impl_rollback_type_tuple!(A, B, C, D, E);
+impl_rollback_type_tuple!(A, B, C, D, E, F);
+impl_rollback_type_tuple!(A, B, C, D, E, F, G);
+impl_rollback_type_tuple!(A, B, C, D, E, F, G, H);
+impl_rollback_type_tuple!(A, B, C, D, E, F, G, H, I);
+impl_rollback_type_tuple!(A, B, C, D, E, F, G, H, I, J);
+impl_rollback_type_tuple!(A, B, C, D, E, F, G, H, I, J, K);
+impl_rollback_type_tuple!(A, B, C, D, E, F, G, H, I, J, K, L);
+
+/// A type with #[rollback(skip)] fields, implemented by #[derive(Rollback)] so the Clone path can
+/// rebuild those fields the same way reflection does.
+pub trait SkippedFields{
+    /// Replaces every skipped field with the one from `fresh`.
+    fn reset_skipped(&mut self, fresh: Self);
+}
+
+/// A field holding entities that #[rollback(entity)] can remap.
+pub trait MapEntity{
+    fn map_entity(&mut self, entity_map: &EntityMap) -> Result<(), MapEntitiesError>;
+}
+
+impl MapEntity for Entity{
+    fn map_entity(&mut self, entity_map: &EntityMap) -> Result<(), MapEntitiesError>{
+        *self = entity_map.get(*self)?;
+        Ok(())
+    }
+}
+
+impl MapEntity for Option<Entity>{
+    fn map_entity(&mut self, entity_map: &EntityMap) -> Result<(), MapEntitiesError>{
+        if let Some(entity) = self{
+            entity.map_entity(entity_map)?;
+        }
+        Ok(())
+    }
+}
+
+impl MapEntity for Vec<Entity>{
+    fn map_entity(&mut self, entity_map: &EntityMap) -> Result<(), MapEntitiesError>{
+        for entity in self.iter_mut(){
+            entity.map_entity(entity_map)?;
+        }
+        Ok(())
+    }
+}
+
+/// A type that can be rebuilt purely from reflected data, including the dynamic data a scene or a
+/// migration produces, so it needs neither FromWorld nor Default. #[derive(Rollback)] implements it
+/// for types marked #[rollback(clone)].
+pub trait FromReflect: Reflect + Sized{
+    fn from_reflect(reflect: &dyn Reflect) -> Option<Self>;
+}
+
+macro_rules! impl_from_reflect_value{
+    ($($name: ty),*) => {
+        $(
+            impl FromReflect for $name{
+                fn from_reflect(reflect: &dyn Reflect) -> Option<Self>{
+                    reflect.any().downcast_ref::<$name>().cloned()
+                }
+            }
+        )*
+    }
+}
+
+impl_from_reflect_value!(bool, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, String);
+impl_from_reflect_value!(Entity, Vec2, Vec3, Vec4, Quat);
+
+impl<T: 'static> FromReflect for Option<T> where Option<T>: Reflect + Clone{
+    fn from_reflect(reflect: &dyn Reflect) -> Option<Self>{
+        reflect.any().downcast_ref::<Option<T>>().cloned()
+    }
+}
+
+impl<T: FromReflect> FromReflect for Vec<T>{
+    fn from_reflect(reflect: &dyn Reflect) -> Option<Self>{
+        match reflect.reflect_ref(){
+            ReflectRef::List(list) => list
+                .iter()
+                .map(T::from_reflect)
+                .collect(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use bevy::prelude::*;
+use bevy::ecs::schedule::ShouldRun;
+use crate::RollbackFrame;
+
+fn new_frame(rollback_frame: Option<Res<RollbackFrame>>) -> ShouldRun{
+    match rollback_frame{
+        Some(rollback_frame) if rollback_frame.is_resimulation => ShouldRun::No,
+        _ => ShouldRun::Yes,
+    }
+}
+
+fn resimulation(rollback_frame: Option<Res<RollbackFrame>>) -> ShouldRun{
+    match rollback_frame{
+        Some(rollback_frame) if rollback_frame.is_resimulation => ShouldRun::Yes,
+        _ => ShouldRun::No,
+    }
+}
+
+fn confirmed_frame(rollback_frame: Option<Res<RollbackFrame>>) -> ShouldRun{
+    match rollback_frame{
+        Some(rollback_frame) if rollback_frame.is_confirmed() => ShouldRun::Yes,
+        _ => ShouldRun::No,
+    }
+}
+
+/// Runs a rollback system only on the first simulation of a frame.
+pub fn on_new_frame() -> impl System<In = (), Out = ShouldRun>{
+    new_frame.system()
+}
+
+/// Runs a rollback system only when a frame is resimulated after a rollback.
+pub fn on_resimulation() -> impl System<In = (), Out = ShouldRun>{
+    resimulation.system()
+}
+
+/// Runs a rollback system only when the frame being simulated is confirmed. A frame that is
+/// confirmed after its last simulation never gets such a run, so confirm frames ahead of
+/// simulating them (e.g. once all inputs for them have arrived) to rely on this.
+pub fn on_confirmed_frame() -> impl System<In = (), Out = ShouldRun>{
+    confirmed_frame.system()
+}
@@ -0,0 +1,398 @@
+use bevy::prelude::*;
+use bevy::reflect::ReflectRef;
+use bevy::reflect::{TypeRegistry, TypeRegistryInternal};
+use bevy::reflect::serde::{ReflectSerializer, ReflectDeserializer};
+use bevy::scene::DynamicScene;
+use bevy::scene::serde::{SceneSerializer, SceneDeserializer};
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+use serde::de::{DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeSeq, SerializeStruct};
+use std::collections::HashMap;
+use crate::err::RollbackError;
+use crate::rollback_registry::RollbackRegistry;
+
+/// A description of a single field of a registered type.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FieldSchema{
+    pub name: String,
+    pub type_name: String,
+}
+
+/// A description of a registered type, built from its fields.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TypeSchema{
+    pub type_name: String,
+    pub kind: String,
+    pub fields: Vec<FieldSchema>,
+    pub entity_mappable: bool,
+    /// The version the type was registered with, schemas saved before versioning read as 0.
+    #[serde(default)]
+    pub version: u32,
+}
+
+impl TypeSchema{
+    /// Describes a type from its field names and field type names, without an instance of it.
+    pub fn from_fields(type_name: &str, kind: &str, fields: &[(&str, &str)]) -> Self{
+        TypeSchema{
+            type_name: type_name.to_owned(),
+            kind: kind.to_owned(),
+            fields: fields
+                .iter()
+                .map(|(name, type_name)| FieldSchema{
+                    name: (*name).to_owned(),
+                    type_name: (*type_name).to_owned(),
+                })
+                .collect(),
+            entity_mappable: false,
+            version: 0,
+        }
+    }
+
+    pub fn from_reflect(value: &dyn Reflect) -> Self{
+        let (kind, fields) = match value.reflect_ref(){
+            ReflectRef::Struct(value) => ("struct", (0..value.field_len())
+                .map(|i| FieldSchema{
+                    name: value.name_at(i).unwrap().to_owned(),
+                    type_name: value.field_at(i).unwrap().type_name().to_owned(),
+                })
+                .collect()),
+            ReflectRef::TupleStruct(value) => ("tuple_struct", (0..value.field_len())
+                .map(|i| FieldSchema{
+                    name: i.to_string(),
+                    type_name: value.field(i).unwrap().type_name().to_owned(),
+                })
+                .collect()),
+            ReflectRef::Tuple(value) => ("tuple", (0..value.field_len())
+                .map(|i| FieldSchema{
+                    name: i.to_string(),
+                    type_name: value.field(i).unwrap().type_name().to_owned(),
+                })
+                .collect()),
+            ReflectRef::List(_) => ("list", Vec::new()),
+            ReflectRef::Map(_) => ("map", Vec::new()),
+            ReflectRef::Value(_) => ("value", Vec::new()),
+        };
+
+        TypeSchema{
+            type_name: value.type_name().to_owned(),
+            kind: kind.to_owned(),
+            fields,
+            entity_mappable: false,
+            version: 0,
+        }
+    }
+}
+
+/// A deterministic description of everything registered in a RollbackRegistry, sorted by type name.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RegistrySchema{
+    pub types: Vec<TypeSchema>,
+}
+
+impl RegistrySchema{
+    /// A stable 64 bit FNV-1a hash of the schema. It doesn't depend on the std hasher, so builds
+    /// with different compilers agree as long as the type names and fields do.
+    pub fn fingerprint(&self) -> u64{
+        let mut hash = Fnv1a::default();
+        for type_schema in self.types.iter(){
+            hash.write(type_schema.type_name.as_bytes());
+            hash.write(type_schema.kind.as_bytes());
+            hash.write(&[type_schema.entity_mappable as u8]);
+            // Unversioned types hash as they did before versions existed.
+            if type_schema.version != 0{
+                hash.write(&type_schema.version.to_le_bytes());
+            }
+            for field in type_schema.fields.iter(){
+                hash.write(field.name.as_bytes());
+                hash.write(field.type_name.as_bytes());
+            }
+        }
+        hash.finish()
+    }
+
+    /// The version a type was saved with, 0 if the schema doesn't know it.
+    pub fn version_of(&self, type_name: &str) -> u32{
+        self.types
+            .iter()
+            .find(|type_schema| type_schema.type_name == type_name)
+            .map(|type_schema| type_schema.version)
+            .unwrap_or(0)
+    }
+}
+
+/// The FNV-1a hash, used wherever a hash has to be the same on every build.
+pub(crate) struct Fnv1a{
+    hash: u64,
+}
+
+impl Default for Fnv1a{
+    fn default() -> Self{
+        Fnv1a{
+            hash: 0xcbf29ce484222325,
+        }
+    }
+}
+
+impl Fnv1a{
+    pub(crate) fn write(&mut self, bytes: &[u8]){
+        for byte in bytes{
+            self.hash ^= *byte as u64;
+            self.hash = self.hash.wrapping_mul(0x100000001b3);
+        }
+        // Separate every write so ("ab", "c") and ("a", "bc") hash differently.
+        self.hash ^= 0xff;
+        self.hash = self.hash.wrapping_mul(0x100000001b3);
+    }
+
+    pub(crate) fn finish(&self) -> u64{
+        self.hash
+    }
+}
+
+/// Type data for describing a registered type without an instance of it at hand.
+#[derive(Clone)]
+pub struct ReflectSchema{
+    describe: fn() -> Result<TypeSchema, RollbackError>,
+}
+
+impl ReflectSchema{
+    /// Describes a type from a function that doesn't need an instance of it, #[derive(Rollback)]
+    /// builds one from the struct's fields.
+    pub fn new(describe: fn() -> Result<TypeSchema, RollbackError>) -> Self{
+        ReflectSchema{
+            describe,
+        }
+    }
+
+    pub fn describe(&self) -> Result<TypeSchema, RollbackError>{
+        (self.describe)()
+    }
+
+    /// Describes a type by name only, for types whose fields aren't known without an instance.
+    /// Every registered type starts out like this until #[derive(Rollback)] or set_schema describes it.
+    pub fn opaque<C: Reflect>() -> Self{
+        ReflectSchema{
+            describe: ||{
+                Ok(TypeSchema{
+                    type_name: std::any::type_name::<C>().to_owned(),
+                    kind: "opaque".to_owned(),
+                    fields: Vec::new(),
+                    entity_mappable: false,
+                    version: 0,
+                })
+            },
+        }
+    }
+}
+
+/// Turns the reflected data of one version of a type into the next version.
+pub type Migration = fn(Box<dyn Reflect>) -> Box<dyn Reflect>;
+
+/// Type data holding the current version of a type and the migrations up to it.
+#[derive(Clone, Default)]
+pub struct ReflectMigrations{
+    version: u32,
+    migrations: HashMap<u32, Migration>,
+}
+
+impl ReflectMigrations{
+    pub fn version(&self) -> u32{
+        self.version
+    }
+
+    pub(crate) fn set_version(&mut self, version: u32){
+        self.version = version;
+    }
+
+    pub(crate) fn add_migration(&mut self, from_version: u32, migration: Migration){
+        self.migrations.insert(from_version, migration);
+    }
+
+    /// Runs every migration from the saved version up to the current one.
+    pub fn migrate(&self, from_version: u32, mut value: Box<dyn Reflect>) -> Result<Box<dyn Reflect>, RollbackError>{
+        if from_version > self.version{
+            return Err(RollbackError::Migration(format!(
+                "{} was saved with version {} but the registered version is {}",
+                value.type_name(), from_version, self.version)));
+        }
+        for version in from_version..self.version{
+            let migration = self
+                .migrations
+                .get(&version)
+                .ok_or_else(|| RollbackError::Migration(format!(
+                    "{} has no migration from version {}",
+                    value.type_name(), version)))?;
+            value = migration(value);
+        }
+        Ok(value)
+    }
+}
+
+/// A world written out for a save or replay, kept together with the schema it was written with
+/// so a later build can migrate it when loading it back, see save_world and load_world.
+pub struct SavedWorld{
+    pub schema: RegistrySchema,
+    pub scene: DynamicScene,
+    pub resources: Vec<Box<dyn Reflect>>,
+}
+
+impl SavedWorld{
+    /// Writes the saved world out as ron. Reflected values are written through the registry, the
+    /// same way Bevy writes scenes.
+    pub fn serialize_ron(&self, registry: &RollbackRegistry) -> Result<String, RollbackError>{
+        bevy::scene::serialize_ron(SavedWorldSerializer{
+            saved_world: self,
+            registry: &registry.registry,
+        }).map_err(|err| RollbackError::Serialization(err.to_string()))
+    }
+
+    /// Reads a saved world back from ron, as written by serialize_ron. Values are left as they were
+    /// saved, load_world migrates them to the current versions.
+    pub fn deserialize_ron(ron: &str, registry: &RollbackRegistry) -> Result<SavedWorld, RollbackError>{
+        let type_registry = registry.registry.read();
+        let mut deserializer = ron::de::Deserializer::from_str(ron)
+            .map_err(|err| RollbackError::Serialization(err.to_string()))?;
+        SavedWorldDeserializer{type_registry: &type_registry}
+            .deserialize(&mut deserializer)
+            .map_err(|err| RollbackError::Serialization(err.to_string()))
+    }
+}
+
+struct SavedWorldSerializer<'a>{
+    saved_world: &'a SavedWorld,
+    registry: &'a TypeRegistry,
+}
+
+impl<'a> Serialize for SavedWorldSerializer<'a>{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>{
+        let mut state = serializer.serialize_struct("SavedWorld", 3)?;
+        state.serialize_field("schema", &self.saved_world.schema)?;
+        state.serialize_field("scene", &SceneSerializer::new(&self.saved_world.scene, self.registry))?;
+        state.serialize_field("resources", &ResourcesSerializer{
+            resources: &self.saved_world.resources,
+            registry: self.registry,
+        })?;
+        state.end()
+    }
+}
+
+struct ResourcesSerializer<'a>{
+    resources: &'a [Box<dyn Reflect>],
+    registry: &'a TypeRegistry,
+}
+
+impl<'a> Serialize for ResourcesSerializer<'a>{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>{
+        let type_registry = self.registry.read();
+        let mut state = serializer.serialize_seq(Some(self.resources.len()))?;
+        for resource in self.resources.iter(){
+            state.serialize_element(&ReflectSerializer::new(&**resource, &type_registry))?;
+        }
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "lowercase")]
+enum SavedWorldField{
+    Schema,
+    Scene,
+    Resources,
+}
+
+struct SavedWorldDeserializer<'a>{
+    type_registry: &'a TypeRegistryInternal,
+}
+
+impl<'a, 'de> DeserializeSeed<'de> for SavedWorldDeserializer<'a>{
+    type Value = SavedWorld;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error>{
+        deserializer.deserialize_struct("SavedWorld", &["schema", "scene", "resources"], self)
+    }
+}
+
+impl<'a, 'de> Visitor<'de> for SavedWorldDeserializer<'a>{
+    type Value = SavedWorld;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result{
+        formatter.write_str("saved world")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error>{
+        let mut schema = None;
+        let mut scene = None;
+        let mut resources = None;
+        while let Some(key) = map.next_key()?{
+            match key{
+                SavedWorldField::Schema => schema = Some(map.next_value()?),
+                SavedWorldField::Scene => scene = Some(map.next_value_seed(SceneDeserializer{
+                    type_registry: self.type_registry,
+                })?),
+                SavedWorldField::Resources => resources = Some(map.next_value_seed(ResourcesDeserializer{
+                    type_registry: self.type_registry,
+                })?),
+            }
+        }
+        Ok(SavedWorld{
+            schema: schema.ok_or_else(|| serde::de::Error::missing_field("schema"))?,
+            scene: scene.ok_or_else(|| serde::de::Error::missing_field("scene"))?,
+            resources: resources.ok_or_else(|| serde::de::Error::missing_field("resources"))?,
+        })
+    }
+}
+
+struct ResourcesDeserializer<'a>{
+    type_registry: &'a TypeRegistryInternal,
+}
+
+impl<'a, 'de> DeserializeSeed<'de> for ResourcesDeserializer<'a>{
+    type Value = Vec<Box<dyn Reflect>>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error>{
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'a, 'de> Visitor<'de> for ResourcesDeserializer<'a>{
+    type Value = Vec<Box<dyn Reflect>>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result{
+        formatter.write_str("list of resources")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error>{
+        let mut resources = Vec::new();
+        while let Some(resource) = seq.next_element_seed(ReflectDeserializer::new(self.type_registry))?{
+            resources.push(resource);
+        }
+        Ok(resources)
+    }
+}
+
+/// Brings every component and resource in a saved world up to date with the current versions.
+pub(crate) fn migrate_saved_world(
+    saved_world: &mut SavedWorld,
+    migrations: impl Fn(&str) -> Option<ReflectMigrations>,
+) -> Result<(), RollbackError>{
+    let saved_schema = &saved_world.schema;
+    let migrate = |value: Box<dyn Reflect>| -> Result<Box<dyn Reflect>, RollbackError>{
+        let type_name = value.type_name().to_owned();
+        match migrations(&type_name){
+            Some(migrations) => migrations.migrate(saved_schema.version_of(&type_name), value),
+            None => Ok(value),
+        }
+    };
+
+    for entity in saved_world.scene.entities.iter_mut(){
+        entity.components = std::mem::take(&mut entity.components)
+            .into_iter()
+            .map(migrate)
+            .collect::<Result<_, _>>()?;
+    }
+    saved_world.resources = std::mem::take(&mut saved_world.resources)
+        .into_iter()
+        .map(migrate)
+        .collect::<Result<_, _>>()?;
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use crate::rollback_buffer::RollbackBuffer;
+use crate::RollbackWorld;
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+/// The identity of one logical side effect. Resimulating a frame emits the same keys as long as
+/// the same things happen, which is how repeated effects are recognised.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct SideEffectKey{
+    pub frame: usize,
+    pub source: u64,
+    pub kind: &'static str,
+    pub index: usize,
+}
+
+/// A resource inside the RollbackWorld that rollback systems emit side effects (sounds, particles, ...) through.
+#[derive(Default)]
+pub struct SideEffectEmitter{
+    frame: usize,
+    counters: HashMap<(u64, &'static str), usize>,
+    frames: HashMap<usize, Vec<SideEffectKey>>,
+}
+
+impl SideEffectEmitter{
+    /// Emits an effect of the given kind from the given source, e.g. the bits of a SpawnKey.
+    pub fn emit(&mut self, source: u64, kind: &'static str) -> SideEffectKey{
+        let counter = self.counters.entry((source, kind)).or_insert(0);
+        let key = SideEffectKey{
+            frame: self.frame,
+            source,
+            kind,
+            index: *counter,
+        };
+        *counter += 1;
+        self.frames
+            .entry(self.frame)
+            .or_default()
+            .push(key);
+        key
+    }
+
+    pub(crate) fn start_frame(&mut self, frame: usize){
+        self.frame = frame;
+        self.counters.clear();
+        self.frames.insert(frame, Vec::new());
+    }
+
+    fn drain(&mut self) -> HashMap<usize, Vec<SideEffectKey>>{
+        std::mem::take(&mut self.frames)
+    }
+}
+
+/// Sent to the outer world once per logical side effect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SideEffectEvent{
+    Fired(SideEffectKey),
+    /// A rollback resimulated the frame and the effect didn't happen again.
+    Cancelled(SideEffectKey),
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct SideEffectSettings{
+    /// When set, effects are only fired once their frame is confirmed, and are never cancelled.
+    pub confirmed_only: bool,
+}
+
+/// The effects delivered for every frame that may still be rolled back.
+#[derive(Default)]
+pub struct SideEffectLedger{
+    frames: HashMap<usize, HashSet<SideEffectKey>>,
+}
+
+impl SideEffectLedger{
+    /// Forgets the effects of every frame, without cancelling or firing them.
+    pub(crate) fn clear(&mut self){
+        self.frames.clear();
+    }
+}
+
+/// Compares the effects of every frame simulated this tick against the ones already delivered,
+/// firing the new ones and cancelling the ones that vanished.
+pub fn deliver_side_effects(
+    mut rollback_world: ResMut<RollbackWorld>,
+    rollback_buffer: Res<RollbackBuffer>,
+    settings: Res<SideEffectSettings>,
+    mut ledger: ResMut<SideEffectLedger>,
+    mut side_effect_events: EventWriter<SideEffectEvent>,
+){
+    let mut simulated = match rollback_world.get_resource_mut::<SideEffectEmitter>(){
+        Some(mut emitter) => emitter.drain().into_iter().collect::<Vec<_>>(),
+        None => Vec::new(),
+    };
+    simulated.sort_by_key(|(frame, _)| *frame);
+
+    for (frame, keys) in simulated{
+        let keys = keys.into_iter().collect::<HashSet<_>>();
+        let previous = ledger.frames.insert(frame, keys.clone()).unwrap_or_default();
+        if settings.confirmed_only{
+            continue;
+        }
+
+        let mut cancelled = previous.difference(&keys).cloned().collect::<Vec<_>>();
+        cancelled.sort();
+        side_effect_events.send_batch(cancelled.into_iter().map(SideEffectEvent::Cancelled));
+
+        let mut fired = keys.difference(&previous).cloned().collect::<Vec<_>>();
+        fired.sort();
+        side_effect_events.send_batch(fired.into_iter().map(SideEffectEvent::Fired));
+    }
+
+    let mut confirmed = ledger.frames
+        .keys()
+        .filter(|frame| rollback_buffer.confirmed_frame().is_some_and(|confirmed_frame| **frame <= confirmed_frame))
+        .cloned()
+        .collect::<Vec<_>>();
+    confirmed.sort();
+    for frame in confirmed{
+        let keys = ledger.frames.remove(&frame).unwrap();
+        if settings.confirmed_only{
+            let mut fired = keys.into_iter().collect::<Vec<_>>();
+            fired.sort();
+            side_effect_events.send_batch(fired.into_iter().map(SideEffectEvent::Fired));
+        }
+    }
+}
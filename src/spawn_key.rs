@@ -0,0 +1,49 @@
+use bevy::reflect::Reflect;
+use crate::schema::Fnv1a;
+use std::collections::HashMap;
+
+/// A deterministic identity for an entity spawned by a rollback system.
+/// Resimulating a frame produces the same keys in the same order, so the outer
+/// entity synced to a predicted spawn can be kept across a rollback.
+#[derive(Default, Reflect, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SpawnKey{
+    pub source: u64,
+    pub frame: usize,
+    pub index: usize,
+}
+
+/// A resource inside the RollbackWorld that hands out SpawnKeys for the frame currently
+/// being simulated. The counters are reset by the rollback system before every frame.
+#[derive(Default)]
+pub struct SpawnKeyGenerator{
+    frame: usize,
+    counters: HashMap<u64, usize>,
+}
+
+impl SpawnKeyGenerator{
+    /// Returns the next key for the given source (usually the name of the spawning system).
+    pub fn next_key(&mut self, source: &str) -> SpawnKey{
+        // The std hasher may change between releases, which would desync peers and saves.
+        let mut hasher = Fnv1a::default();
+        hasher.write(source.as_bytes());
+        let source = hasher.finish();
+
+        let counter = self.counters.entry(source).or_insert(0);
+        let key = SpawnKey{
+            source,
+            frame: self.frame,
+            index: *counter,
+        };
+        *counter += 1;
+        key
+    }
+
+    pub fn frame(&self) -> usize{
+        self.frame
+    }
+
+    pub(crate) fn start_frame(&mut self, frame: usize){
+        self.frame = frame;
+        self.counters.clear();
+    }
+}
@@ -1,28 +1,73 @@
-use crate::util::{overwrite_world};
+use crate::util::{overwrite_world, clear_world, copy_resources};
 use bevy::tasks::ComputeTaskPool;
 use crate::rollback_registry::RollbackRegistry;
 use crate::rollback_schedule::{RollbackSchedule, RollbackStartupSchedule};
 use crate::rollback_buffer::RollbackBuffer;
-use crate::RollbackWorld;
+use crate::spawn_key::{SpawnKey, SpawnKeyGenerator};
+use crate::side_effect::SideEffectEmitter;
+use crate::control::{CatchUpPolicy, RollbackClock, RollbackControl, RollbackTickRate};
+use crate::side_effect::SideEffectLedger;
+use crate::{RollbackWorld, RollbackFrame};
+use crate::err::RollbackError;
 use bevy::prelude::*;
+use bevy::app::Events;
+use bevy::ecs::component::Component;
+use std::collections::{HashMap, HashSet};
+use std::any::TypeId;
 
 pub(crate) fn rollback_system(
     mut current_world: ResMut<RollbackWorld>,
     mut rollback_buffer: ResMut<RollbackBuffer>,
     mut rollback_schedule: ResMut<RollbackSchedule>,
     rollback_registry: Res<RollbackRegistry>,
+    catch_up: Option<Res<CatchUpPolicy>>,
+    clock: Option<ResMut<RollbackClock>>,
+    failures: Option<ResMut<Events<RollbackFailed>>>,
 ){
-    if rollback_buffer.rollback_needed() > 0{
-        let target = rollback_buffer.current_frame() as isize - rollback_buffer.rollback_needed();
-        let rollback_world = rollback_buffer.get_world_mut(target as usize).expect("Couldn't find world in buffer");
-        overwrite_world(&rollback_world, &mut current_world, &rollback_registry).unwrap();
+    // More ticks follow this one in the current render frame, so its snapshots may be skipped.
+    let skip_snapshots = match (catch_up, clock){
+        (Some(catch_up), Some(mut clock)) => clock.run_tick() && catch_up.skip_intermediate_snapshots,
+        _ => false,
+    };
+
+    let mut restored = rollback_buffer.rollback_needed() > 0;
+    let mut start = rollback_buffer.current_frame() as isize - rollback_buffer.rollback_needed();
+    if restored{
+        match restore_snapshot(start as usize, &mut current_world, &rollback_buffer, &rollback_registry){
+            Ok(restored_start) => start = restored_start as isize,
+            Err(error) => {
+                // Without a snapshot to resimulate from, only the new frame is run.
+                error!("Couldn't roll back: {:?}", error);
+                if let Some(mut failures) = failures{
+                    failures.send(RollbackFailed{
+                        frame: start as usize,
+                        error,
+                    });
+                }
+                restored = false;
+                start = rollback_buffer.current_frame() as isize;
+            }
+        }
     }
-    for target in (rollback_buffer.current_frame() as isize - rollback_buffer.rollback_needed())..=rollback_buffer.current_frame() as isize{
+    for target in start..=rollback_buffer.current_frame() as isize{
+        rollback_registry.update_events(&mut current_world);
         if let Some(overrides) = rollback_buffer.get_override_mut(&(target as isize)){
             overrides.run(&mut current_world);
         }
-        rollback_buffer.push_world(&(target as usize), &current_world, &rollback_registry).unwrap();
-        rollback_schedule.run_once(&mut current_world);
+        if !skip_snapshots || rollback_buffer.snapshot_due(target as usize){
+            if let Err(err) = rollback_buffer.push_world(&(target as usize), &current_world, &rollback_registry){
+                // The frame still runs, a rollback into it resimulates from an earlier snapshot.
+                error!("Couldn't snapshot frame {}: {:?}", target, err);
+                rollback_buffer.skip_world(target as usize);
+            }
+        }else if !(restored && target == start){
+            // The world that was just restored from stays, so it can be restored again.
+            rollback_buffer.skip_world(target as usize);
+        }
+        run_frame(target as usize, &mut current_world, &rollback_buffer, &mut rollback_schedule);
+        record_vanished(target as usize, &current_world, &mut rollback_buffer);
+        let events = rollback_registry.record_events(&current_world);
+        rollback_buffer.push_events(target as usize, events);
     }
 
     rollback_buffer.reset_rollback_needed();
@@ -30,6 +75,62 @@ pub(crate) fn rollback_system(
     rollback_buffer.inc_frame();
 }
 
+/// Sent when a rollback to the given frame couldn't be done, usually because it's older than every
+/// snapshot left in the buffer. The frames in between aren't resimulated and the overrides queued
+/// for them never run, so the game should resync its state.
+#[derive(Debug)]
+pub struct RollbackFailed{
+    pub frame: usize,
+    pub error: RollbackError,
+}
+
+/// Restores the latest snapshot at or before the given frame into the RollbackWorld, returning
+/// the frame it was taken on.
+fn restore_snapshot(
+    frame: usize,
+    current_world: &mut World,
+    rollback_buffer: &RollbackBuffer,
+    rollback_registry: &RollbackRegistry,
+) -> Result<usize, RollbackError>{
+    let start = rollback_buffer
+        .latest_world_before(frame)
+        .ok_or(RollbackError::MissingSnapshot(frame))?;
+    let rollback_world = rollback_buffer.get_world(start).unwrap();
+    overwrite_world(rollback_world, current_world, rollback_registry)?;
+    // Readers see the events sent late in the frame before the restored one, as they did then.
+    let recorded = start.checked_sub(1).and_then(|previous| rollback_buffer.frame_events(previous));
+    rollback_registry.restore_events(current_world, recorded);
+    Ok(start)
+}
+
+/// Notes the synced rollback entities that are gone after the given frame, so the outer entities
+/// know which frame to wait on when their despawn is deferred.
+fn record_vanished(frame: usize, current_world: &World, rollback_buffer: &mut RollbackBuffer){
+    if let Some(synced_entity_map) = current_world.get_resource::<SyncedEntityMap>(){
+        for entity in synced_entity_map.rollback_entities(){
+            if current_world.get_entity(entity).is_none(){
+                rollback_buffer.record_vanished(entity, frame);
+            }
+        }
+    }
+}
+
+/// Runs the rollback schedule once for the given frame.
+fn run_frame(frame: usize, current_world: &mut World, rollback_buffer: &RollbackBuffer, rollback_schedule: &mut RollbackSchedule){
+    current_world
+        .get_resource_or_insert_with(SpawnKeyGenerator::default)
+        .start_frame(frame);
+    current_world
+        .get_resource_or_insert_with(SideEffectEmitter::default)
+        .start_frame(frame);
+    current_world.insert_resource(RollbackFrame{
+        frame,
+        is_resimulation: frame < rollback_buffer.current_frame(),
+        confirmed_frame: rollback_buffer.confirmed_frame(),
+    });
+    rollback_schedule.run_once(current_world);
+}
+
 /// A component on a rollback entity to mark if it's been synced.
 pub(crate) struct SyncedRollback;
 
@@ -38,29 +139,160 @@ pub struct Synced{
     pub target: Entity,
 }
 
+/// A two way map between rollback entities and the outer entities synced to them. It lives in the
+/// outer world and a copy is kept in the RollbackWorld, so both sides can look each other up.
+#[derive(Default, Clone, Debug)]
+pub struct SyncedEntityMap{
+    outer: HashMap<Entity, Entity>,
+    rollback: HashMap<Entity, Entity>,
+}
+
+impl SyncedEntityMap{
+    /// Gets the outer entity synced to the given rollback entity.
+    pub fn outer_entity(&self, rollback_entity: Entity) -> Option<Entity>{
+        self.outer.get(&rollback_entity).cloned()
+    }
+
+    /// Gets the rollback entity the given outer entity is synced to.
+    pub fn rollback_entity(&self, outer_entity: Entity) -> Option<Entity>{
+        self.rollback.get(&outer_entity).cloned()
+    }
+
+    pub fn len(&self) -> usize{
+        self.outer.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.outer.is_empty()
+    }
+
+    pub(crate) fn insert(&mut self, rollback_entity: Entity, outer_entity: Entity){
+        self.remove_outer(outer_entity);
+        self.outer.insert(rollback_entity, outer_entity);
+        self.rollback.insert(outer_entity, rollback_entity);
+    }
+
+    pub(crate) fn rollback_entities(&self) -> impl Iterator<Item = Entity> + '_{
+        self.outer.keys().copied()
+    }
+
+    pub(crate) fn remove_outer(&mut self, outer_entity: Entity){
+        if let Some(rollback_entity) = self.rollback.remove(&outer_entity){
+            self.outer.remove(&rollback_entity);
+        }
+    }
+}
+
+/// Settings for how outer entities follow their rollback targets.
+#[derive(Default, Clone, Debug)]
+pub struct SyncSettings{
+    /// When set, an outer entity with a SpawnKey is only despawned once the frame its target
+    /// vanished in is confirmed, since until then a rollback may revive it. Outer entities
+    /// without a SpawnKey can't be matched to a revived target, so they are still despawned
+    /// right away.
+    pub defer_despawn: bool,
+}
+
+/// A component on an outer world entity whose target vanished on the given frame.
+pub struct PendingDespawn{
+    pub frame: usize,
+}
+
+/// Sent when an outer entity starts or stops waiting on a deferred despawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncedDespawnEvent{
+    Pending(Entity),
+    Cancelled(Entity),
+}
+
+/// Spawns an outer entity for every new rollback entity and despawns outer entities whose target is gone.
+/// Rollback entities with a SpawnKey are matched against orphaned outer entities with the same key,
+/// so a spawn that survives resimulation keeps its outer entity.
 pub fn sync_rollback_entities(
     mut commands: Commands,
     mut rollback_world: ResMut<RollbackWorld>,
-    synced: Query<(Entity, &Synced)>,
+    mut rollback_buffer: ResMut<RollbackBuffer>,
+    sync_settings: Res<SyncSettings>,
+    mut synced_entity_map: ResMut<SyncedEntityMap>,
+    mut despawn_events: EventWriter<SyncedDespawnEvent>,
+    mut synced: Query<(Entity, &mut Synced, Option<&SpawnKey>, Option<&PendingDespawn>)>,
 ){
-    for (entity, synced) in synced.iter(){
-        if let None = rollback_world.get_entity(synced.target){
-            commands
-                .entity(entity)
-                .despawn();
+    let mut orphaned = HashMap::new();
+
+    for (entity, synced, spawn_key, pending) in synced.iter_mut(){
+        if rollback_world.get_entity(synced.target).is_none(){
+            // Several ticks may have run since the last sync, the rollback system noted which
+            // one the target vanished in.
+            let vanished_frame = rollback_buffer
+                .take_vanished(synced.target)
+                .unwrap_or_else(|| rollback_buffer.current_frame().saturating_sub(1));
+            match spawn_key{
+                Some(spawn_key) => {
+                    orphaned.insert(*spawn_key, (entity, pending.map(|pending| pending.frame), vanished_frame));
+                },
+                None => {
+                    commands
+                        .entity(entity)
+                        .despawn();
+                }
+            }
+            synced_entity_map.remove_outer(entity);
         }
     }
 
     let mut syncable = Vec::new();
 
-    for entity in rollback_world.query_filtered::<Entity, Without<SyncedRollback>>().iter(&mut rollback_world){
-        commands
-            .spawn()
-            .insert(
-                Synced{target: entity.clone()}
-            );
-            
-        syncable.push(entity.clone());
+    for (entity, spawn_key) in rollback_world.query_filtered::<(Entity, Option<&SpawnKey>), Without<SyncedRollback>>().iter(&rollback_world){
+        match spawn_key.and_then(|spawn_key| orphaned.remove(spawn_key)){
+            Some((outer, pending, _)) => {
+                if let Ok((_, mut synced, _, _)) = synced.get_mut(outer){
+                    synced.target = entity;
+                }
+                synced_entity_map.insert(entity, outer);
+                if pending.is_some(){
+                    commands
+                        .entity(outer)
+                        .remove::<PendingDespawn>();
+                    despawn_events.send(SyncedDespawnEvent::Cancelled(outer));
+                }
+            },
+            None => {
+                let mut outer = commands.spawn();
+                outer.insert(Synced{target: entity});
+                if let Some(spawn_key) = spawn_key{
+                    outer.insert(*spawn_key);
+                }
+                synced_entity_map.insert(entity, outer.id());
+            }
+        }
+
+        syncable.push(entity);
+    }
+
+    for (_, (entity, pending, vanished_frame)) in orphaned{
+        if !sync_settings.defer_despawn{
+            commands
+                .entity(entity)
+                .despawn();
+            continue;
+        }
+        match pending{
+            Some(frame) => {
+                // A frame ahead of the current one was left behind by a restart.
+                let confirmed = rollback_buffer.confirmed_frame().is_some_and(|confirmed_frame| frame <= confirmed_frame);
+                if frame >= rollback_buffer.current_frame() || confirmed{
+                    commands
+                        .entity(entity)
+                        .despawn();
+                }
+            },
+            None => {
+                commands
+                    .entity(entity)
+                    .insert(PendingDespawn{frame: vanished_frame});
+                despawn_events.send(SyncedDespawnEvent::Pending(entity));
+            }
+        }
     }
 
     for syncable in syncable{
@@ -68,11 +300,180 @@ pub fn sync_rollback_entities(
             .entity_mut(syncable)
             .insert(SyncedRollback);
     }
+
+    rollback_world.insert_resource(synced_entity_map.clone());
+}
+
+/// Mirrors the Parent of every synced rollback entity onto its outer entity, so the outer world
+/// carries the same hierarchy as the RollbackWorld. The changes are applied right away, so a
+/// parent update running after it in the same stage sees them.
+pub fn sync_rollback_hierarchy(world: &mut World){
+    let synced_entity_map = match world.get_resource::<SyncedEntityMap>(){
+        Some(synced_entity_map) => synced_entity_map.clone(),
+        None => return,
+    };
+
+    let mut synced = world.query::<(Entity, &Synced, Option<&Parent>)>();
+    let changes = {
+        let rollback_world = world
+            .get_resource::<RollbackWorld>()
+            .expect("Add RollbackWorld to app!");
+        synced
+            .iter(world)
+            .filter_map(|(entity, synced, parent)|{
+                let target_parent = rollback_world
+                    .get::<Parent>(synced.target)
+                    .and_then(|parent| synced_entity_map.outer_entity(parent.0));
+                match (target_parent, parent){
+                    (Some(target_parent), Some(parent)) if target_parent == parent.0 => None,
+                    (None, None) => None,
+                    (target_parent, _) => Some((entity, target_parent)),
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+
+    for (entity, target_parent) in changes{
+        match target_parent{
+            Some(target_parent) => {
+                world
+                    .entity_mut(entity)
+                    .insert(Parent(target_parent));
+            },
+            None => {
+                world
+                    .entity_mut(entity)
+                    .remove::<Parent>();
+            }
+        }
+    }
+}
+
+/// Copies a mirrored component from rollback entities onto their outer entities, removing it
+/// from outer entities whose target no longer has it.
+pub(crate) fn mirror_component<T: Component + Clone>(rollback_world: &mut World, world: &mut World){
+    let synced_entity_map = match world.get_resource::<SyncedEntityMap>(){
+        Some(synced_entity_map) => synced_entity_map.clone(),
+        None => return,
+    };
+
+    let mirrored = rollback_world
+        .query::<(Entity, &T)>()
+        .iter(rollback_world)
+        .filter_map(|(entity, component)| synced_entity_map
+            .outer_entity(entity)
+            .map(|outer| (outer, component.clone())))
+        .collect::<Vec<_>>();
+
+    let stale = world
+        .query_filtered::<(Entity, &Synced), With<T>>()
+        .iter(world)
+        .filter(|(_, synced)| rollback_world.get::<T>(synced.target).is_none())
+        .map(|(entity, _)| entity)
+        .collect::<Vec<_>>();
+
+    for (outer, component) in mirrored{
+        if let Some(mut outer) = world.get_entity_mut(outer){
+            outer.insert(component);
+        }
+    }
+
+    for outer in stale{
+        world
+            .entity_mut(outer)
+            .remove::<T>();
+    }
+}
+
+/// Runs every mirror registered in the RollbackRegistry.
+pub fn mirror_rollback_components(world: &mut World){
+    let mirrored = world
+        .get_resource::<RollbackRegistry>()
+        .expect("Add RollbackRegistry to app!")
+        .mirrored
+        .clone();
+
+    world.resource_scope(|world, mut rollback_world: Mut<RollbackWorld>|{
+        for mirror in mirrored{
+            mirror(&mut rollback_world, world);
+        }
+    });
+}
+
+/// The resources inserted into the RollbackWorld with insert_rollback_resource while building the
+/// app. They are copied out before the rollback startup schedule first runs, and a restart puts
+/// them back.
+#[derive(Default)]
+pub struct InitialRollbackResources{
+    types: HashSet<TypeId>,
+    world: World,
+}
+
+impl InitialRollbackResources{
+    pub(crate) fn add<T: Component>(&mut self){
+        self.types.insert(TypeId::of::<T>());
+    }
 }
 
 pub fn rollback_startup(
     mut rollback_world: ResMut<RollbackWorld>,
     mut rollback_startup_schedule: ResMut<RollbackStartupSchedule>,
+    rollback_registry: Res<RollbackRegistry>,
+    initial_resources: Option<ResMut<InitialRollbackResources>>,
 ){
+    if let Some(mut initial_resources) = initial_resources{
+        let InitialRollbackResources{types, world} = &mut *initial_resources;
+        if let Err(err) = copy_resources(&rollback_world, world, types, &rollback_registry){
+            error!("Couldn't keep the initial rollback resources for restarts: {:?}", err);
+        }
+    }
     rollback_startup_schedule.run(&mut rollback_world);
-}
\ No newline at end of file
+
+    let audit = rollback_registry.audit(&rollback_world);
+    if !audit.is_empty(){
+        warn!("{}", audit);
+    }
+}
+
+/// Carries out a restart requested through RollbackControl.
+pub fn restart_rollback(world: &mut World){
+    let restart = world
+        .get_resource_mut::<RollbackControl>()
+        .is_some_and(|mut control| control.take_restart());
+    if !restart{
+        return;
+    }
+
+    world.resource_scope(|world, mut rollback_world: Mut<RollbackWorld>|{
+        let rollback_registry = world
+            .get_resource::<RollbackRegistry>()
+            .expect("Add RollbackRegistry to app!");
+        // The tick rate may have been changed while playing, the next match keeps it.
+        let tick_rate = rollback_world.get_resource::<RollbackTickRate>().copied();
+        if let Err(err) = clear_world(&mut rollback_world, rollback_registry){
+            error!("Couldn't clear the RollbackWorld for a restart: {:?}", err);
+        }
+        if let Some(initial_resources) = world.get_resource::<InitialRollbackResources>(){
+            if let Err(err) = copy_resources(&initial_resources.world, &mut rollback_world, &initial_resources.types, rollback_registry){
+                error!("Couldn't put the initial rollback resources back for a restart: {:?}", err);
+            }
+        }
+        if let Some(tick_rate) = tick_rate{
+            rollback_world.insert_resource(tick_rate);
+        }
+        rollback_registry.clear_events(&mut rollback_world);
+
+        world
+            .get_resource_mut::<RollbackBuffer>()
+            .expect("Add RollbackBuffer to app!")
+            .reset();
+        world
+            .get_resource_mut::<RollbackStartupSchedule>()
+            .expect("Add RollbackStartupSchedule to app!")
+            .run(&mut rollback_world);
+    });
+
+    if let Some(mut ledger) = world.get_resource_mut::<SideEffectLedger>(){
+        ledger.clear();
+    }
+}
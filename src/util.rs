@@ -1,18 +1,25 @@
 use bevy::ecs::schedule::Stage;
 use crate::RollbackStartupSchedule;
-use crate::rollback_schedule::RollbackSchedule;
-use bevy::app::AppBuilder;
-use bevy::ecs::schedule::{StageLabel, SystemDescriptor};
+use crate::rollback_schedule::{RollbackSchedule, RollbackScheduleStage};
+use bevy::app::{AppBuilder, Events};
+use bevy::ecs::schedule::{StageLabel, SystemDescriptor, SystemSet};
 use bevy::reflect::TypeRegistry;
 use bevy::ecs::reflect::ReflectMapEntities;
-use crate::reflect_resource::ReflectResource;
+use bevy::ecs::archetype::ArchetypeId;
+use crate::reflect_resource::{ReflectResource, ReflectRemoveComponent, ReflectMapEntitiesResources, ReflectClone, ReflectFromReflectComponent};
+use std::collections::HashSet;
+use std::any::{Any, TypeId};
+use bevy::reflect::GetTypeRegistration;
+use bevy::ecs::entity::{MapEntities, MapEntitiesError};
 use crate::rollback_registry::RollbackRegistry;
 use crate::RollbackWorld;
 use crate::err::RollbackError;
+use crate::schema::SavedWorld;
+use crate::system::InitialRollbackResources;
 
 use bevy::{
     ecs::reflect::{ReflectComponent, ReflectMut},
-    scene::{DynamicScene, Entity},
+    scene::DynamicScene,
     reflect::{Reflect, FromType},
     ecs::world::{World, FromWorld},
     ecs::component::Component,
@@ -29,6 +36,30 @@ pub fn clone_rollback_world_entities(source_world: &World, target_world: &mut Wo
         }
 
         for component_id in archetype.components() {
+            let type_id = source_world
+                .components()
+                .get_info(component_id)
+                .and_then(|info| info.type_id());
+            if let Some(true) = type_id.map(|type_id| registry.non_rolling.contains(&type_id)){
+                continue;
+            }
+
+            let reflect_clone = type_id
+                .and_then(|type_id| type_registry.get(type_id))
+                .and_then(|registration| registration.data::<ReflectClone>());
+            if let Some(reflect_clone) = reflect_clone{
+                for entity in archetype.entities(){
+                    reflect_clone
+                        .copy_component(
+                            source_world,
+                            target_world,
+                            *entity,
+                            entity_map.get(*entity).unwrap(),
+                        )
+                }
+                continue;
+            }
+
             let reflect_component = source_world
                 .components()
                 .get_info(component_id)
@@ -52,25 +83,25 @@ pub fn clone_rollback_world_entities(source_world: &World, target_world: &mut Wo
                                 entity_map.get(entity.clone()).unwrap(),
                             )
                     },
-                Err(id) => {
-                    if let None = registry.unregisterable.get(&id){
-                        return Err(RollbackError::UnregisteredType(source_world
-                            .components()
-                            .get_info(component_id)
-                            .unwrap()
-                            .name()
-                            .to_owned()));
-                    }
-                }
+                Err(id) => registry.handle_unregistered(
+                    id,
+                    source_world
+                        .components()
+                        .get_info(component_id)
+                        .unwrap()
+                        .name())?,
             };
         }
     }
 
     for registration in type_registry.iter() {
+        if registry.non_rolling.contains(&registration.type_id()){
+            continue;
+        }
         if let Some(map_entities_reflect) = registration.data::<ReflectMapEntities>() {
             map_entities_reflect
                 .map_entities(target_world, &entity_map)
-                .unwrap();
+                .map_err(|err| RollbackError::MapEntities(registration.name().to_owned(), err))?;
         }
     }
 
@@ -82,6 +113,25 @@ pub fn clone_rollback_world_resources(source_world: &World, target_world: &mut W
     let archetype = source_world.archetypes().resource();
 
     for component_id in archetype.unique_components().indices(){
+        let type_id = source_world
+            .components()
+            .get_info(component_id)
+            .and_then(|info| info.type_id());
+        if let Some(true) = type_id.map(|type_id| registry.non_rolling.contains(&type_id)){
+            continue;
+        }
+
+        let reflect_clone = type_id
+            .and_then(|type_id| type_registry.get(type_id))
+            .and_then(|registration| registration.data::<ReflectClone>());
+        if let Some(reflect_clone) = reflect_clone{
+            reflect_clone.copy_resource(
+                source_world,
+                target_world,
+            );
+            continue;
+        }
+
         let reflect_resource = source_world
             .components()
             .get_info(component_id)
@@ -101,34 +151,87 @@ pub fn clone_rollback_world_resources(source_world: &World, target_world: &mut W
                     target_world,
                 )
             }
-            Err(id) => {
-                if let None = registry.unregisterable.get(&id){
-                    return Err(RollbackError::UnregisteredType(source_world
-                        .components()
-                        .get_info(component_id)
-                        .unwrap()
-                        .name()
-                        .to_owned()));
-                }
-            }
+            Err(id) => registry.handle_unregistered(
+                id,
+                source_world
+                    .components()
+                    .get_info(component_id)
+                    .unwrap()
+                    .name())?,
         }
     }
 
     for registration in type_registry.iter() {
-        if let Some(map_entities_reflect) = registration.data::<ReflectMapEntities>() {
-            map_entities_reflect
-                .map_entities(target_world, &entity_map)
-                .unwrap();
+        if registry.non_rolling.contains(&registration.type_id()){
+            continue;
+        }
+        if let Some(map_entities_reflect) = registration.data::<ReflectMapEntitiesResources>() {
+            // A resource may still point at an entity that has been despawned since, like the last
+            // target of something. Those are left pointing where they did instead of failing.
+            let mut dangling = Vec::new();
+            let mapped = loop{
+                match map_entities_reflect.map_entities(target_world, entity_map){
+                    Err(MapEntitiesError::EntityNotFound(entity)) if !dangling.contains(&entity) => {
+                        dangling.push(entity);
+                        entity_map.insert(entity, entity);
+                        // Start over from the source, the failed pass may have mapped some entities already.
+                        if let Some(reflect_clone) = registration.data::<ReflectClone>(){
+                            reflect_clone.copy_resource(source_world, target_world);
+                        }else if let Some(reflect_resource) = registration.data::<ReflectResource>(){
+                            reflect_resource.copy_resource(source_world, target_world);
+                        }
+                    },
+                    mapped => break mapped,
+                }
+            };
+            for entity in dangling{
+                entity_map.remove(entity);
+            }
+            mapped.map_err(|err| RollbackError::MapEntities(registration.name().to_owned(), err))?;
         }
     }
+
     return Ok(());
 }
 
+/// Copies the given rolled back resources from one world to another, leaving out the ones the
+/// source world doesn't have.
+pub(crate) fn copy_resources(source_world: &World, target_world: &mut World, types: &HashSet<TypeId>, registry: &RollbackRegistry) -> Result<(), RollbackError>{
+    let type_registry = registry.registry.read();
+    for component_id in source_world.archetypes().resource().unique_components().indices(){
+        let info = source_world.components().get_info(component_id).unwrap();
+        let type_id = match info.type_id(){
+            Some(type_id) if types.contains(&type_id) && !registry.non_rolling.contains(&type_id) => type_id,
+            _ => continue,
+        };
+        let registration = type_registry.get(type_id);
+        if let Some(reflect_clone) = registration.and_then(|registration| registration.data::<ReflectClone>()){
+            reflect_clone.copy_resource(source_world, target_world);
+        }else if let Some(reflect_resource) = registration.and_then(|registration| registration.data::<ReflectResource>()){
+            reflect_resource.copy_resource(source_world, target_world);
+        }else{
+            registry.handle_unregistered(type_id, info.name())?;
+        }
+    }
+    Ok(())
+}
+
+/// A component on every snapshot entity holding the live entity it was cloned from.
+pub(crate) struct SnapshotOf(pub(crate) bevy::ecs::entity::Entity);
+
 pub fn clone_world(source_world: &World, registry: &RollbackRegistry) -> Result<World, RollbackError>{
     let mut target_world = World::default();
     let mut entity_map = EntityMap::default();
     clone_rollback_world_entities(source_world, &mut target_world, &mut entity_map, &registry)?;
     clone_rollback_world_resources(source_world, &mut target_world, &mut entity_map, &registry)?;
+    for source_entity in entity_map.keys(){
+        target_world
+            .entity_mut(entity_map.get(source_entity).unwrap())
+            .insert(SnapshotOf(source_entity));
+    }
+    for hook in registry.type_hooks(|hooks| hooks.on_snapshot){
+        hook(&mut target_world);
+    }
     Ok(target_world)
 }
 
@@ -149,6 +252,14 @@ pub fn clear_resources(world: &mut World, registry: &RollbackRegistry) -> Result
     let mut removals = Vec::new();
 
     for component_id in archetype.unique_components().indices(){
+        let type_id = world
+            .components()
+            .get_info(component_id)
+            .and_then(|info| info.type_id());
+        if let Some(true) = type_id.map(|type_id| registry.non_rolling.contains(&type_id)){
+            continue;
+        }
+
         let reflect_resource = world
             .components()
             .get_info(component_id)
@@ -165,16 +276,13 @@ pub fn clear_resources(world: &mut World, registry: &RollbackRegistry) -> Result
             Ok(reflect_resource) =>{
                 removals.push(reflect_resource);
             }
-            Err(id) => {
-                if let None = registry.unregisterable.get(&id){
-                    return Err(RollbackError::UnregisteredType(world
-                        .components()
-                        .get_info(component_id)
-                        .unwrap()
-                        .name()
-                        .to_owned()));
-                }
-            }
+            Err(id) => registry.handle_unregistered(
+                id,
+                world
+                    .components()
+                    .get_info(component_id)
+                    .unwrap()
+                    .name())?,
         }
     }
 
@@ -191,14 +299,228 @@ pub fn clear_world(world: &mut World, registry: &RollbackRegistry) -> Result<(),
     clear_resources(world, registry)
 }
 
-pub fn overwrite_world(source_world: &World, target_world: &mut World, registry: &RollbackRegistry) -> Result<(), RollbackError>{
-    clear_world(target_world, registry)?;
+/// Strips the rolled back components from every live entity that still exists in the snapshot
+/// and despawns the rest, returning the map from snapshot entities to the retained live ones.
+fn retain_entities(source_world: &World, target_world: &mut World, registry: &RollbackRegistry) -> EntityMap{
+    let type_registry = registry.registry.read();
     let mut entity_map = EntityMap::default();
+
+    for archetype in source_world.archetypes().iter(){
+        for entity in archetype.entities(){
+            if let Some(snapshot_of) = source_world.get::<SnapshotOf>(*entity){
+                if target_world.get_entity(snapshot_of.0).is_some(){
+                    entity_map.insert(*entity, snapshot_of.0);
+                }
+            }
+        }
+    }
+    let retained = entity_map.values().collect::<HashSet<_>>();
+
+    let mut removals = Vec::new();
+    let mut despawns = Vec::new();
+    for archetype in target_world.archetypes().iter(){
+        for entity in archetype.entities(){
+            if !retained.contains(entity){
+                despawns.push(*entity);
+                continue;
+            }
+            for component_id in archetype.components(){
+                let remove_component = target_world
+                    .components()
+                    .get_info(component_id)
+                    .and_then(|info| info.type_id())
+                    .filter(|type_id| !registry.non_rolling.contains(type_id))
+                    .and_then(|type_id| type_registry.get(type_id))
+                    .and_then(|registration| registration.data::<ReflectRemoveComponent>());
+                if let Some(remove_component) = remove_component{
+                    removals.push((remove_component.clone(), *entity));
+                }
+            }
+        }
+    }
+
+    for entity in despawns{
+        target_world.despawn(entity);
+    }
+    for (remove_component, entity) in removals{
+        remove_component.remove_component(target_world, entity);
+    }
+
+    entity_map
+}
+
+/// Restores the source snapshot into the target world. Entities that survive keep their ids and
+/// their local-only components, everything rolled back is replaced with the snapshot's data.
+pub fn overwrite_world(source_world: &World, target_world: &mut World, registry: &RollbackRegistry) -> Result<(), RollbackError>{
+    clear_resources(target_world, registry)?;
+    let mut entity_map = retain_entities(source_world, target_world, registry);
     clone_rollback_world_entities(source_world, target_world, &mut entity_map, &registry)?;
     clone_rollback_world_resources(source_world, target_world, &mut entity_map, &registry)?;
+    for hook in registry.type_hooks(|hooks| hooks.on_restore){
+        hook(target_world);
+    }
+    for hook in registry.on_restored.iter(){
+        hook(target_world);
+    }
     Ok(())
 }
 
+/// Builds a DynamicScene of every entity in the world, like DynamicScene::from_world, but also
+/// covering types registered without FromWorld. Types left out of snapshots are left out here too.
+pub fn scene_from_world(world: &World, registry: &RollbackRegistry) -> Result<DynamicScene, RollbackError>{
+    let type_registry = registry.registry.read();
+    let mut scene = DynamicScene::default();
+
+    for archetype in world.archetypes().iter(){
+        if archetype.id() == ArchetypeId::resource(){
+            continue;
+        }
+        let entities_offset = scene.entities.len();
+        for entity in archetype.entities(){
+            scene.entities.push(bevy::scene::Entity{
+                entity: entity.id(),
+                components: Vec::new(),
+            });
+        }
+
+        for component_id in archetype.components(){
+            let info = world.components().get_info(component_id).unwrap();
+            let type_id = info.type_id();
+            if type_id.is_some_and(|type_id| registry.non_rolling.contains(&type_id)){
+                continue;
+            }
+            let registration = type_id.and_then(|type_id| type_registry.get(type_id));
+            let reflect_component = registration.and_then(|registration| registration.data::<ReflectComponent>());
+            let reflect_from_reflect = registration.and_then(|registration| registration.data::<ReflectFromReflectComponent>());
+
+            if reflect_component.is_none() && reflect_from_reflect.is_none(){
+                match type_id{
+                    Some(type_id) => registry.handle_unregistered(type_id, info.name())?,
+                    None => return Err(RollbackError::UnregisteredType(info.name().to_owned())),
+                }
+                continue;
+            }
+
+            for (i, entity) in archetype.entities().iter().enumerate(){
+                let component = match reflect_component{
+                    Some(reflect_component) => reflect_component.reflect_component(world, *entity),
+                    None => reflect_from_reflect.and_then(|reflect_from_reflect| reflect_from_reflect.reflect_component(world, *entity)),
+                };
+                if let Some(component) = component{
+                    scene.entities[entities_offset + i]
+                        .components
+                        .push(component.clone_value());
+                }
+            }
+        }
+    }
+
+    Ok(scene)
+}
+
+/// Spawns the entities of a scene into the world, rebuilding types registered without FromWorld
+/// from their reflected data. Returns the map from scene entities to the spawned ones.
+pub fn spawn_scene(scene: &DynamicScene, world: &mut World, registry: &RollbackRegistry) -> Result<EntityMap, RollbackError>{
+    let type_registry = registry.registry.read();
+    let mut entity_map = EntityMap::default();
+
+    for scene_entity in scene.entities.iter(){
+        let entity = *entity_map
+            .entry(bevy::ecs::entity::Entity::new(scene_entity.entity))
+            .or_insert_with(|| world.spawn().id());
+        for component in scene_entity.components.iter(){
+            let registration = type_registry
+                .get_with_name(component.type_name())
+                .ok_or_else(|| RollbackError::UnregisteredType(component.type_name().to_owned()))?;
+            if let Some(reflect_component) = registration.data::<ReflectComponent>(){
+                reflect_component.add_component(world, entity, &**component);
+            }else if let Some(reflect_from_reflect) = registration.data::<ReflectFromReflectComponent>(){
+                reflect_from_reflect.add_component(world, entity, &**component);
+            }else{
+                return Err(RollbackError::UnregisteredType(component.type_name().to_owned()));
+            }
+        }
+    }
+
+    for registration in type_registry.iter(){
+        if let Some(map_entities_reflect) = registration.data::<ReflectMapEntities>(){
+            map_entities_reflect
+                .map_entities(world, &entity_map)
+                .map_err(|err| RollbackError::MapEntities(registration.name().to_owned(), err))?;
+        }
+    }
+
+    Ok(entity_map)
+}
+
+/// Writes out every rolled back entity and resource in the world together with the registry's
+/// current schema, for a save file or replay.
+pub fn save_world(world: &World, registry: &RollbackRegistry) -> Result<SavedWorld, RollbackError>{
+    let schema = registry.schema()?;
+    let scene = scene_from_world(world, registry)?;
+
+    let type_registry = registry.registry.read();
+    let mut resources = Vec::new();
+    for component_id in world.archetypes().resource().unique_components().indices(){
+        let info = world.components().get_info(component_id).unwrap();
+        let type_id = info.type_id().unwrap();
+        if registry.non_rolling.contains(&type_id){
+            continue;
+        }
+        let reflect_resource = type_registry
+            .get(type_id)
+            .and_then(|registration| registration.data::<ReflectResource>());
+        match reflect_resource{
+            Some(reflect_resource) => resources.extend(reflect_resource
+                .reflect_resource(world)
+                .map(|resource| resource.clone_value())),
+            None => registry.handle_unregistered(type_id, info.name())?,
+        }
+    }
+
+    Ok(SavedWorld{
+        schema,
+        scene,
+        resources,
+    })
+}
+
+/// Loads a saved world into the given world, first migrating everything in it from the versions it
+/// was saved with to the current ones. Returns the map from saved entities to the spawned ones.
+pub fn load_world(mut saved_world: SavedWorld, world: &mut World, registry: &RollbackRegistry) -> Result<EntityMap, RollbackError>{
+    registry.migrate(&mut saved_world)?;
+    let entity_map = spawn_scene(&saved_world.scene, world, registry)?;
+
+    let type_registry = registry.registry.read();
+    for resource in saved_world.resources.iter(){
+        type_registry
+            .get_with_name(resource.type_name())
+            .and_then(|registration| registration.data::<ReflectResource>())
+            .ok_or_else(|| RollbackError::UnregisteredType(resource.type_name().to_owned()))?
+            .add_resource(world, &**resource);
+    }
+
+    for registration in type_registry.iter(){
+        if let Some(map_entities_reflect) = registration.data::<ReflectMapEntitiesResources>(){
+            map_entities_reflect
+                .map_entities(world, &entity_map)
+                .map_err(|err| RollbackError::MapEntities(registration.name().to_owned(), err))?;
+        }
+    }
+
+    Ok(entity_map)
+}
+
+/// save_world written out as ron, see SavedWorld::serialize_ron.
+pub fn save_world_ron(world: &World, registry: &RollbackRegistry) -> Result<String, RollbackError>{
+    save_world(world, registry)?.serialize_ron(registry)
+}
+
+/// Reads a world written by save_world_ron and loads it like load_world, migrating it first.
+pub fn load_world_ron(ron: &str, world: &mut World, registry: &RollbackRegistry) -> Result<EntityMap, RollbackError>{
+    load_world(SavedWorld::deserialize_ron(ron, registry)?, world, registry)
+}
+
 pub trait AppBuilderRollbackUtil{
     fn add_rollback_startup_stage<S: Stage>(
         &mut self,
@@ -251,6 +573,45 @@ pub trait AppBuilderRollbackUtil{
         label: impl StageLabel,
         system: impl Into<SystemDescriptor>
     ) -> &mut AppBuilder;
+
+    /// Adds a system to the update stage of the RollbackSchedule.
+    fn add_rollback_system(
+        &mut self,
+        system: impl Into<SystemDescriptor>
+    ) -> &mut AppBuilder;
+
+    fn add_rollback_system_set_to_stage(
+        &mut self,
+        label: impl StageLabel,
+        system_set: SystemSet
+    ) -> &mut AppBuilder;
+
+    /// Adds a system set to the update stage of the RollbackSchedule.
+    fn add_rollback_system_set(
+        &mut self,
+        system_set: SystemSet
+    ) -> &mut AppBuilder;
+
+    fn register_rollback_component<T: Any + Reflect + GetTypeRegistration + FromWorld>(
+        &mut self
+    ) -> &mut AppBuilder;
+
+    fn register_rollback_resource<T: Any + Reflect + GetTypeRegistration + FromWorld>(
+        &mut self
+    ) -> &mut AppBuilder;
+
+    fn register_rollback_entity_mappable<T: Any + Reflect + GetTypeRegistration + FromWorld + MapEntities>(
+        &mut self
+    ) -> &mut AppBuilder;
+
+    fn insert_rollback_resource<T: Component>(
+        &mut self,
+        resource: T
+    ) -> &mut AppBuilder;
+
+    fn add_rollback_event<T: Component + Clone>(
+        &mut self
+    ) -> &mut AppBuilder;
 }
 
 impl AppBuilderRollbackUtil for AppBuilder{
@@ -369,4 +730,97 @@ impl AppBuilderRollbackUtil for AppBuilder{
 
         self
     }
-}
\ No newline at end of file
+
+    fn add_rollback_system(
+        &mut self,
+        system: impl Into<SystemDescriptor>
+    ) -> &mut AppBuilder {
+        self.add_rollback_system_to_stage(RollbackScheduleStage::Update, system)
+    }
+
+    fn add_rollback_system_set_to_stage(
+        &mut self,
+        label: impl StageLabel,
+        system_set: SystemSet
+    ) -> &mut AppBuilder {
+        self
+            .world_mut()
+            .get_resource_mut::<RollbackSchedule>()
+            .expect("Add RollbackSchedule to app!")
+            .add_system_set_to_stage(label, system_set);
+
+        self
+    }
+
+    fn add_rollback_system_set(
+        &mut self,
+        system_set: SystemSet
+    ) -> &mut AppBuilder {
+        self.add_rollback_system_set_to_stage(RollbackScheduleStage::Update, system_set)
+    }
+
+    fn register_rollback_component<T: Any + Reflect + GetTypeRegistration + FromWorld>(
+        &mut self
+    ) -> &mut AppBuilder {
+        self
+            .world_mut()
+            .get_resource_mut::<RollbackRegistry>()
+            .expect("Add RollbackRegistry to app!")
+            .register::<T>();
+
+        self
+    }
+
+    fn register_rollback_resource<T: Any + Reflect + GetTypeRegistration + FromWorld>(
+        &mut self
+    ) -> &mut AppBuilder {
+        self
+            .world_mut()
+            .get_resource_mut::<RollbackRegistry>()
+            .expect("Add RollbackRegistry to app!")
+            .register::<T>();
+
+        self
+    }
+
+    fn register_rollback_entity_mappable<T: Any + Reflect + GetTypeRegistration + FromWorld + MapEntities>(
+        &mut self
+    ) -> &mut AppBuilder {
+        self
+            .world_mut()
+            .get_resource_mut::<RollbackRegistry>()
+            .expect("Add RollbackRegistry to app!")
+            .register_entity_mappable::<T>();
+
+        self
+    }
+
+    fn insert_rollback_resource<T: Component>(
+        &mut self,
+        resource: T
+    ) -> &mut AppBuilder {
+        self
+            .world_mut()
+            .get_resource_mut::<RollbackWorld>()
+            .expect("Add RollbackWorld to app!")
+            .insert_resource(resource);
+        self
+            .world_mut()
+            .get_resource_or_insert_with(InitialRollbackResources::default)
+            .add::<T>();
+
+        self
+    }
+
+    fn add_rollback_event<T: Component + Clone>(
+        &mut self
+    ) -> &mut AppBuilder {
+        self
+            .world_mut()
+            .get_resource_mut::<RollbackRegistry>()
+            .expect("Add RollbackRegistry to app!")
+            .register_rollback_event::<T>();
+
+        self.insert_rollback_resource(Events::<T>::default())
+    }
+}